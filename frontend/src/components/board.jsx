@@ -1,4 +1,3 @@
-import { gameSize } from '../constants';
 import leftArrow from './left-arrow.png';
 import rightArrow from './right-arrow.png';
 
@@ -26,6 +25,8 @@ function Slot({ yourTurn, direction, onSlotClick }) {
       {/*Yeah, this direction is a bit weird, but seemed most intuitive during playtesting */}
       {direction === 'left' && <img src={rightArrow}/>}
       {direction === 'right' && <img src={leftArrow}/> }
+      {direction === 'down' && '▼'}
+      {direction === 'up' && '▲'}
     </div>
   );
 }
@@ -50,14 +51,20 @@ export function InfoBar({ message, colour, yourColour, yourName, theirName }) {
   );
 }
 
-export function Board({ yourTurn, squares, onSlotClick}) {
+export function Board({ yourTurn, squares, winLines, ruleset, onSlotClick}) {
+  const winning = new Set(
+    winLines.flatMap(line => line.squares.map(([row, col]) => `${row},${col}`))
+  );
+
   function handleClick(rowNum, fallingDirection) {
     if (!yourTurn) {
       return;
     }
-    const row = squares[rowNum];
+    const row = ['up', 'down'].includes(fallingDirection)
+      ? squares.map(cells => cells[rowNum])
+      : squares[rowNum];
     let colNum;
-    if (fallingDirection === 'right') {
+    if (fallingDirection === 'right' || fallingDirection === 'down') {
       colNum = row.findIndex(element => element === null);
     }
     else {
@@ -72,27 +79,47 @@ export function Board({ yourTurn, squares, onSlotClick}) {
     }
   }
 
+  // Four-sided games can also be played into the top and bottom of every column
+  const columnSlots = (direction) => ruleset === 'foursided' && (
+    <div className="board-row">
+      <div className="slot"/>
+      {
+        squares[0].map((_, col) =>
+          <Slot key={col} yourTurn={yourTurn} direction={direction} onSlotClick={() => handleClick(col, direction)}/>
+        )
+      }
+      <div className="slot"/>
+    </div>
+  );
+
   return (
     <div className="board">
+    {columnSlots('down')}
     {
-      [...Array(gameSize).keys()].map((row) => {
+      squares.map((cells, row) => {
         return (
           <div key={row} className="board-row">
             <Slot yourTurn={yourTurn} direction="right" onSlotClick={() => handleClick(row, "right")}/>
             {
-              [...Array(gameSize).keys()].map((col) => {
+              cells.map((cell, col) => {
                 return <Square
                          key={col}
-                         value={squares[row][col]?.value}
-                         fallingDirection={squares[row][col]?.direction}
+                         value={cell?.value}
+                         fallingDirection={winning.has(`${row},${col}`) ? 'win' : cell?.direction}
                        />;
               })
             }
-            <Slot yourTurn={yourTurn} direction="left"  onSlotClick={() => handleClick(row, "left")}/>
+            {
+              // Classic games only stack from one side
+              ruleset === 'classic'
+                ? <div className="slot"/>
+                : <Slot yourTurn={yourTurn} direction="left"  onSlotClick={() => handleClick(row, "left")}/>
+            }
           </div>
         );
       })
     }
+    {columnSlots('up')}
     </div>
   );
 }
@@ -7,13 +7,12 @@ const base_host = import.meta.env.VITE_STACKED_FOURSIDE_HOST || 'localhost:4321'
 const protocol = import.meta.env.VITE_STACKED_FOURSIDE_HOST ? 'https' : 'http';
 
 function GameState({ username, onGameEnd }) {
-  const [squares, setSquares] = useState(Array(gameSize).fill(null).map(() => Array(gameSize).fill(null)));
-  const [colour, setColour] = useState(null);
-  const [yourColour, setYourColour] = useState(null);
-  const [yourTurn, setYourTurn] = useState(false);
-  const [message, setMessage] = useState('Please wait...');
-  const [yourName, setYourName] = useState();
-  const [theirName, setTheirName] = useState();
+  // Every game we've been sent, by id, and the one being looked at
+  const [games, setGames] = useState({});
+  const [gameId, setGameId] = useState(null);
+  const [notice, setNotice] = useState('Please wait...');
+  const [seeks, setSeeks] = useState([]);
+  const [inviteUrl, setInviteUrl] = useState(null);
   const socketRef = useRef(null);
 
   useEffect(() => {
@@ -45,19 +44,55 @@ function GameState({ username, onGameEnd }) {
           play: null
         };
         socketRef.current.send(JSON.stringify(msg));
+        // Opened from an invite link, so join the friend's game
+        const invite = new URLSearchParams(window.location.search).get('invite');
+        if (invite) {
+          socketRef.current.send(JSON.stringify({ join: invite }));
+        }
       };
 
       socketRef.current.onmessage = ({data}) => {
         const game = JSON.parse(data);
 
+        if (game.error) {
+          if (game.game_id) {
+            setGames((games) => games[game.game_id] ? {
+              ...games,
+              [game.game_id]: { ...games[game.game_id], message: `Invalid move: ${game.error}` },
+            } : games);
+          } else {
+            setNotice(game.error);
+          }
+          return;
+        }
+
+        if (game.seeks) {
+          setSeeks(game.seeks);
+          setNotice(null);
+          return;
+        }
+
         const yourTurn = game.current_player === game.your_colour;
 
         let message;
         if (game.current_player) {
           message = yourTurn ? 'It is your turn. What is your move?' : `Waiting for ${game.current_player}'s turn`;
+          if (game.takeback_by && game.takeback_by !== game.your_colour) {
+            message = `${game.their_name} wants to take back their last move`;
+          } else if (game.draw_offered_by && game.draw_offered_by !== game.your_colour) {
+            message = `${game.their_name} offers a draw`;
+          }
         } else {
           onGameEnd();
-          if (game.winner) {
+          if (game.result === 'abandoned') {
+            message = game.winner === game.your_colour
+              ? `${game.their_name} left, so you win!`
+              : 'The game was abandoned';
+          } else if (game.result === 'resigned') {
+            message = game.winner === game.your_colour
+              ? `${game.their_name} resigned, so you win!`
+              : 'You resigned';
+          } else if (game.winner) {
             message = game.winner === game.your_colour ? 'You win!' : 'You lose...';
           } 
           else {
@@ -65,13 +100,13 @@ function GameState({ username, onGameEnd }) {
           }
         }
 
-        setSquares(game.squares);
-        setColour(game.current_player);
-        setYourColour(game.your_colour);
-        setYourTurn(yourTurn);
-        setMessage(message);
-        setYourName(game.your_name);
-        setTheirName(game.their_name);
+        setGames((games) => {
+          // Show a game as soon as it starts
+          if (!games[game.game_id]) {
+            setGameId(game.game_id);
+          }
+          return { ...games, [game.game_id]: { ...game, yourTurn, message } };
+        });
       };
     }
     setupSocket();
@@ -82,30 +117,133 @@ function GameState({ username, onGameEnd }) {
 
   function handleSlotClick(rowNum, direction) {
     const msg = {
-      request: 'play',
+      game_id: gameId,
       play: [rowNum, direction],
     };
     socketRef.current.send(JSON.stringify(msg));
   }
 
+  function sendTakeback(takeback) {
+    socketRef.current.send(JSON.stringify({ game_id: gameId, takeback }));
+  }
+
+  function sendDraw(draw) {
+    socketRef.current.send(JSON.stringify({ game_id: gameId, draw }));
+  }
+
+  function resign() {
+    socketRef.current.send(JSON.stringify({ game_id: gameId, resign: true }));
+  }
+
+  function sendLobby(msg) {
+    socketRef.current.send(JSON.stringify(msg));
+  }
+
+  async function createInvite() {
+    const response = await fetch(
+      `${protocol}://${base_host}/invites`,
+      {
+        method: 'POST',
+        body: JSON.stringify({ username, ruleset }),
+        headers: {
+          "Content-Type": "application/json",
+        },
+        mode: 'cors',
+      }
+    );
+    const json = await response.json();
+    setInviteUrl(json.url);
+  }
+
+  const yourSeek = seeks.find((seek) => seek.player === username);
+
+  const game = games[gameId];
+  const squares = game?.squares ?? Array(gameSize).fill(null).map(() => Array(gameSize).fill(null));
+  const ruleset = game?.ruleset ?? 'stacked';
+  const colour = game?.current_player;
+  const yourColour = game?.your_colour;
+  const takebackBy = game?.takeback_by;
+  const drawOfferedBy = game?.draw_offered_by;
+  const finished = game && !game.current_player;
 
   return (
     <div className="game">
       <div className="game-board">
+        {Object.keys(games).length > 1 && (
+          <div className="game-tabs">
+            {Object.values(games).map((other) => (
+              <button
+                key={other.game_id}
+                onClick={() => setGameId(other.game_id)}
+                disabled={other.game_id === gameId}
+              >
+                vs {other.their_name}{other.yourTurn ? ' (your turn)' : ''}
+              </button>
+            ))}
+          </div>
+        )}
         <InfoBar
-          message={message}
+          message={game?.message ?? notice ?? 'Accept a seek from the lobby, or post your own'}
           colour={colour}
           yourColour={yourColour}
-          yourName={yourName}
-          theirName={theirName}
+          yourName={game?.your_name}
+          theirName={game?.their_name}
         />
         <Board
-          yourTurn={yourTurn}
+          yourTurn={game?.yourTurn ?? false}
           colour={colour}
           squares={squares}
+          winLines={game?.win_lines ?? []}
+          ruleset={ruleset}
           onSlotClick={handleSlotClick}
         />
       </div>
+      {!finished && yourColour && (
+        <div className="game-info">
+          {takebackBy && takebackBy !== yourColour ? (
+            <>
+              <button onClick={() => sendTakeback('accept')}>accept takeback</button>
+              <button onClick={() => sendTakeback('decline')}>decline</button>
+            </>
+          ) : (
+            <button onClick={() => sendTakeback('request')} disabled={takebackBy === yourColour}>
+              {takebackBy === yourColour ? 'takeback asked for...' : 'take back'}
+            </button>
+          )}
+          {drawOfferedBy && drawOfferedBy !== yourColour ? (
+            <>
+              <button onClick={() => sendDraw('accept')}>accept draw</button>
+              <button onClick={() => sendDraw('decline')}>decline</button>
+            </>
+          ) : (
+            <button onClick={() => sendDraw('offer')} disabled={drawOfferedBy === yourColour}>
+              {drawOfferedBy === yourColour ? 'draw offered...' : 'offer a draw'}
+            </button>
+          )}
+          <button onClick={resign}>resign</button>
+        </div>
+      )}
+      <div className="game-info lobby">
+        {yourSeek ? (
+          <button onClick={() => sendLobby({ cancel_seek: yourSeek.id })}>cancel your seek</button>
+        ) : (
+          <button onClick={() => sendLobby({ seek: { ruleset } })}>post a seek</button>
+        )}
+        {inviteUrl ? (
+          <p>Send this link to a friend: <a href={inviteUrl}>{inviteUrl}</a></p>
+        ) : (
+          <button onClick={createInvite}>invite a friend</button>
+        )}
+        {game && notice && <p>{notice}</p>}
+        <ul>
+          {seeks.filter((seek) => seek !== yourSeek).map((seek) => (
+            <li key={seek.id}>
+              {seek.player}: {seek.rows}x{seek.columns}, {seek.win_length} in a row, {seek.ruleset}
+              <button onClick={() => sendLobby({ accept_seek: seek.id })}>play</button>
+            </li>
+          ))}
+        </ul>
+      </div>
     </div>
   );
 }
@@ -12,6 +12,21 @@ pub struct Model {
     pub player_red_id: Option<i32>,
     pub player_black_id: Option<i32>,
     pub finished: bool,
+    pub rows: i32,
+    pub columns: i32,
+    pub win_length: i32,
+    pub ruleset: String,
+    pub ply: i32,
+    pub version: i32,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+    pub finished_at: Option<DateTimeWithTimeZone>,
+    pub result: Option<String>,
+    pub winner_id: Option<i32>,
+    pub takeback_by: Option<i32>,
+    pub analysis: bool,
+    pub active_at: DateTimeWithTimeZone,
+    pub draw_offered_by: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -32,6 +47,14 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     PlayerBlack,
+    #[sea_orm(
+        belongs_to = "super::player::Entity",
+        from = "Column::WinnerId",
+        to = "super::player::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    Winner,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
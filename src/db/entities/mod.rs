@@ -3,4 +3,7 @@
 pub mod prelude;
 
 pub mod game;
+pub mod game_move;
+pub mod invite;
 pub mod player;
+pub mod seek;
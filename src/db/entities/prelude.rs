@@ -1,4 +1,7 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.3
 
 pub use super::game::Entity as Game;
+pub use super::game_move::Entity as Move;
+pub use super::invite::Entity as Invite;
 pub use super::player::Entity as Player;
+pub use super::seek::Entity as Seek;
@@ -0,0 +1,417 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::entities::{game, game_move, invite, player, seek};
+use super::{DbError, GameStore, NewMove};
+use crate::game::{Board, GameSettings};
+
+/// Keeps everything in memory, for running the server without a database and for tests.
+/// Nothing survives a restart.
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    players: Vec<player::Model>,
+    games: BTreeMap<i32, game::Model>,
+    moves: Vec<game_move::Model>,
+    /// Move ids aren't reused once moves are taken back, like the database's
+    last_move_id: i32,
+    seeks: BTreeMap<i32, seek::Model>,
+    /// Seek ids aren't reused once they're taken down, like the database's
+    last_seek_id: i32,
+    invites: Vec<invite::Model>,
+    last_invite_id: i32,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        let mut data = Data::default();
+        // Ensure AI players are ID 1 and 2, like the database does
+        data.player("AI");
+        data.player("HAL9000");
+        MemoryStore {
+            data: Mutex::new(data),
+        }
+    }
+}
+
+impl Data {
+    fn player(&mut self, username: &str) -> player::Model {
+        if let Some(player) = self.players.iter().find(|player| player.name == username) {
+            return player.clone();
+        }
+        let player = player::Model {
+            id: self.players.len() as i32 + 1,
+            name: username.to_owned(),
+        };
+        self.players.push(player.clone());
+        player
+    }
+
+    fn create_empty_game(
+        &mut self,
+        player_id: i32,
+        player2_id: Option<i32>,
+        settings: &GameSettings,
+        analysis: bool,
+        created_at: DateTime<Utc>,
+    ) -> game::Model {
+        let id = self.games.keys().next_back().map_or(1, |id| id + 1);
+        let board = Board::empty(settings);
+        let game = game::Model {
+            id,
+            squares: serde_json::to_value(board.squares()).unwrap(),
+            player_red_id: Some(player_id),
+            player_black_id: player2_id,
+            finished: false,
+            rows: settings.rows as i32,
+            columns: settings.columns as i32,
+            win_length: settings.win_length as i32,
+            ruleset: settings.ruleset.as_str().to_owned(),
+            ply: 0,
+            version: 0,
+            created_at: created_at.into(),
+            updated_at: Utc::now().into(),
+            finished_at: None,
+            result: None,
+            winner_id: None,
+            takeback_by: None,
+            analysis,
+            active_at: Utc::now().into(),
+            draw_offered_by: None,
+        };
+        self.games.insert(id, game.clone());
+        game
+    }
+
+    fn save_game(&mut self, game: game::Model) -> Result<(), DbError> {
+        match self.games.get_mut(&game.id) {
+            Some(saved) if saved.version == game.version => {
+                saved.squares = game.squares;
+                saved.finished = game.finished;
+                saved.ply = game.ply;
+                saved.version += 1;
+                saved.updated_at = Utc::now().into();
+                saved.finished_at = game.finished_at;
+                saved.result = game.result;
+                saved.winner_id = game.winner_id;
+                saved.takeback_by = game.takeback_by;
+                saved.active_at = game.active_at;
+                saved.draw_offered_by = game.draw_offered_by;
+                Ok(())
+            }
+            _ => Err(DbError::Conflict(game.id)),
+        }
+    }
+}
+
+#[async_trait]
+impl GameStore for MemoryStore {
+    async fn get_player(&self, username: &str) -> Result<player::Model, DbError> {
+        Ok(self.data.lock().unwrap().player(username))
+    }
+
+    async fn get_player_by_id(&self, player_id: i32) -> Result<player::Model, DbError> {
+        let data = self.data.lock().unwrap();
+        data.players
+            .iter()
+            .find(|player| player.id == player_id)
+            .cloned()
+            .ok_or(DbError::PlayerNotFound(player_id))
+    }
+
+    async fn find_player(&self, username: &str) -> Result<Option<player::Model>, DbError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .players
+            .iter()
+            .find(|player| player.name == username)
+            .cloned())
+    }
+
+    async fn find_game(&self, game_id: i32) -> Result<Option<game::Model>, DbError> {
+        Ok(self.data.lock().unwrap().games.get(&game_id).cloned())
+    }
+
+    async fn get_finished_games(&self, player_id: i32) -> Result<Vec<game::Model>, DbError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .games
+            .values()
+            .filter(|game| {
+                game.finished
+                    && !game.analysis
+                    && (game.player_red_id == Some(player_id)
+                        || game.player_black_id == Some(player_id))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_inactive_games(&self, before: DateTime<Utc>) -> Result<Vec<game::Model>, DbError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .games
+            .values()
+            .filter(|game| !game.finished && !game.analysis && game.active_at < before)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_player_games(&self, player_id: i32) -> Result<Vec<game::Model>, DbError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .games
+            .values()
+            .filter(|game| {
+                !game.finished
+                    && !game.analysis
+                    && game.player_black_id.is_some()
+                    && (game.player_red_id == Some(player_id)
+                        || game.player_black_id == Some(player_id))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn create_seek(
+        &self,
+        player_id: i32,
+        settings: &GameSettings,
+    ) -> Result<seek::Model, DbError> {
+        let mut data = self.data.lock().unwrap();
+        data.seeks.retain(|_, seek| seek.player_id != player_id);
+        data.last_seek_id += 1;
+        let seek = seek::Model {
+            id: data.last_seek_id,
+            player_id,
+            rows: settings.rows as i32,
+            columns: settings.columns as i32,
+            win_length: settings.win_length as i32,
+            ruleset: settings.ruleset.as_str().to_owned(),
+            created_at: Utc::now().into(),
+        };
+        data.seeks.insert(seek.id, seek.clone());
+        Ok(seek)
+    }
+
+    async fn get_seeks(&self) -> Result<Vec<(seek::Model, player::Model)>, DbError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .seeks
+            .values()
+            .filter_map(|seek| {
+                let player = data
+                    .players
+                    .iter()
+                    .find(|player| player.id == seek.player_id)?;
+                Some((seek.clone(), player.clone()))
+            })
+            .collect())
+    }
+
+    async fn find_seek(&self, seek_id: i32) -> Result<Option<seek::Model>, DbError> {
+        Ok(self.data.lock().unwrap().seeks.get(&seek_id).cloned())
+    }
+
+    async fn cancel_seek(&self, seek_id: i32, player_id: i32) -> Result<bool, DbError> {
+        let mut data = self.data.lock().unwrap();
+        match data.seeks.get(&seek_id) {
+            Some(seek) if seek.player_id == player_id => {
+                data.seeks.remove(&seek_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn cancel_player_seek(&self, player_id: i32) -> Result<bool, DbError> {
+        let mut data = self.data.lock().unwrap();
+        let count = data.seeks.len();
+        data.seeks.retain(|_, seek| seek.player_id != player_id);
+        Ok(data.seeks.len() < count)
+    }
+
+    async fn expire_seeks(&self, before: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut data = self.data.lock().unwrap();
+        let count = data.seeks.len();
+        data.seeks.retain(|_, seek| seek.created_at >= before);
+        Ok(count - data.seeks.len())
+    }
+
+    async fn accept_seek(
+        &self,
+        seek_id: i32,
+        player_id: i32,
+    ) -> Result<Option<game::Model>, DbError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(seek) = data.seeks.remove(&seek_id) else {
+            return Ok(None);
+        };
+        data.seeks
+            .retain(|_, other| other.player_id != seek.player_id && other.player_id != player_id);
+        Ok(Some(data.create_empty_game(
+            seek.player_id,
+            Some(player_id),
+            &seek.settings(),
+            false,
+            Utc::now(),
+        )))
+    }
+
+    async fn create_invite(
+        &self,
+        player_id: i32,
+        code: &str,
+        settings: &GameSettings,
+        expires_at: DateTime<Utc>,
+    ) -> Result<invite::Model, DbError> {
+        let mut data = self.data.lock().unwrap();
+        data.invites.retain(|invite| invite.expires_at > Utc::now());
+        if data.invites.iter().any(|invite| invite.code == code) {
+            return Err(DbError::CodeTaken(code.to_owned()));
+        }
+        data.last_invite_id += 1;
+        let invite = invite::Model {
+            id: data.last_invite_id,
+            code: code.to_owned(),
+            player_id,
+            rows: settings.rows as i32,
+            columns: settings.columns as i32,
+            win_length: settings.win_length as i32,
+            ruleset: settings.ruleset.as_str().to_owned(),
+            created_at: Utc::now().into(),
+            expires_at: expires_at.into(),
+        };
+        data.invites.push(invite.clone());
+        Ok(invite)
+    }
+
+    async fn find_invite(&self, code: &str) -> Result<Option<invite::Model>, DbError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .invites
+            .iter()
+            .find(|invite| invite.code == code && invite.expires_at > Utc::now())
+            .cloned())
+    }
+
+    async fn join_invite(
+        &self,
+        code: &str,
+        player_id: i32,
+    ) -> Result<Option<game::Model>, DbError> {
+        let mut data = self.data.lock().unwrap();
+        let Some(i) = data
+            .invites
+            .iter()
+            .position(|invite| invite.code == code && invite.expires_at > Utc::now())
+        else {
+            return Ok(None);
+        };
+        let invite = data.invites.remove(i);
+        data.seeks
+            .retain(|_, seek| seek.player_id != invite.player_id && seek.player_id != player_id);
+        Ok(Some(data.create_empty_game(
+            invite.player_id,
+            Some(player_id),
+            &invite.settings(),
+            false,
+            Utc::now(),
+        )))
+    }
+
+    async fn get_ai_game(&self, settings: &GameSettings) -> Result<game::Model, DbError> {
+        let mut data = self.data.lock().unwrap();
+        let game = data.games.values().find(|game| {
+            game.player_red_id == Some(1)
+                && game.player_black_id == Some(2)
+                && !game.finished
+                && !game.analysis
+        });
+        if let Some(game) = game {
+            return Ok(game.clone());
+        }
+
+        Ok(data.create_empty_game(1, Some(2), settings, false, Utc::now()))
+    }
+
+    async fn create_analysis_game(
+        &self,
+        player_red_id: i32,
+        player_black_id: i32,
+        settings: &GameSettings,
+        created_at: DateTime<Utc>,
+    ) -> Result<game::Model, DbError> {
+        Ok(self.data.lock().unwrap().create_empty_game(
+            player_red_id,
+            Some(player_black_id),
+            settings,
+            true,
+            created_at,
+        ))
+    }
+
+    async fn save_game(&self, game: game::Model) -> Result<(), DbError> {
+        self.data.lock().unwrap().save_game(game)
+    }
+
+    async fn save_game_and_moves(
+        &self,
+        game: game::Model,
+        taken_back: Option<usize>,
+        moves: &[NewMove],
+    ) -> Result<(), DbError> {
+        let mut data = self.data.lock().unwrap();
+        let game_id = game.id;
+        data.save_game(game)?;
+        if let Some(ply) = taken_back {
+            data.moves
+                .retain(|record| record.game_id != game_id || record.ply < ply as i32);
+        }
+        for (ply, player_id, play) in moves {
+            data.last_move_id += 1;
+            let id = data.last_move_id;
+            data.moves.push(game_move::Model {
+                id,
+                game_id,
+                ply: *ply as i32,
+                player_id: *player_id,
+                row: play.line as i32,
+                direction: play.direction.as_str().to_owned(),
+                pop: play.pop,
+                played_at: Utc::now().into(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn get_moves(&self, game_id: i32) -> Result<Vec<game_move::Model>, DbError> {
+        let mut moves: Vec<game_move::Model> = self
+            .data
+            .lock()
+            .unwrap()
+            .moves
+            .iter()
+            .filter(|record| record.game_id == game_id)
+            .cloned()
+            .collect();
+        moves.sort_by_key(|record| record.ply);
+        Ok(moves)
+    }
+}
+
+/// Starts a game on the default board between two players, found or made by name, the
+/// way the lobby does, for tests that need one to play in.
+#[cfg(test)]
+pub async fn start_game(db: &dyn GameStore, red: &str, black: &str) -> game::Model {
+    let red = db.get_player(red).await.unwrap().id;
+    let black = db.get_player(black).await.unwrap().id;
+    let seek = db.create_seek(red, &GameSettings::default()).await.unwrap();
+    db.accept_seek(seek.id, black).await.unwrap().unwrap()
+}
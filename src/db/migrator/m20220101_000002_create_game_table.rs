@@ -62,4 +62,19 @@ pub enum Game {
     PlayerRedId,
     PlayerBlackId,
     Finished,
+    Rows,
+    Columns,
+    WinLength,
+    Ruleset,
+    Ply,
+    Version,
+    CreatedAt,
+    UpdatedAt,
+    FinishedAt,
+    Result,
+    WinnerId,
+    TakebackBy,
+    Analysis,
+    ActiveAt,
+    DrawOfferedBy,
 }
@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000002_create_game_table::Game;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One column per statement, since not every backend can alter several at once
+        for (column, default) in [(Game::Rows, 7), (Game::Columns, 7), (Game::WinLength, 4)] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Game::Table)
+                        .add_column(ColumnDef::new(column).integer().not_null().default(default))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [Game::Rows, Game::Columns, Game::WinLength] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Game::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000001_create_player_table::Player;
+use super::m20220101_000002_create_game_table::Game;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Move::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Move::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Move::GameId).integer().not_null())
+                    .col(ColumnDef::new(Move::Ply).integer().not_null())
+                    .col(ColumnDef::new(Move::PlayerId).integer().not_null())
+                    .col(ColumnDef::new(Move::Row).integer().not_null())
+                    .col(ColumnDef::new(Move::Direction).string().not_null())
+                    .col(
+                        ColumnDef::new(Move::Pop)
+                            .boolean()
+                            .default(false)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Move::PlayedAt)
+                            .timestamp_with_time_zone()
+                            .default(Expr::current_timestamp())
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-move-game-id")
+                            .from(Move::Table, Move::GameId)
+                            .to(Game::Table, Game::Id),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-move-player-id")
+                            .from(Move::Table, Move::PlayerId)
+                            .to(Player::Table, Player::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-move-game-id-ply")
+                    .table(Move::Table)
+                    .col(Move::GameId)
+                    .col(Move::Ply)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Move::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Move {
+    Table,
+    Id,
+    GameId,
+    Ply,
+    PlayerId,
+    Row,
+    Direction,
+    Pop,
+    PlayedAt,
+}
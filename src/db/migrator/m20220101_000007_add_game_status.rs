@@ -0,0 +1,105 @@
+use chrono::Utc;
+use sea_orm::DbBackend;
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000001_create_player_table::Player;
+use super::m20220101_000002_create_game_table::Game;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let backend = manager.get_database_backend();
+        // SQLite can't add a column that defaults to the current time, so there the
+        // games already played are stamped with the time of the migration instead.
+        // New games always have their times set when they're created.
+        let now: SimpleExpr = match backend {
+            DbBackend::Sqlite => Utc::now().format("%Y-%m-%d %H:%M:%S").to_string().into(),
+            _ => Expr::current_timestamp().into(),
+        };
+        for column in [Game::CreatedAt, Game::UpdatedAt] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Game::Table)
+                        .add_column(
+                            ColumnDef::new(column)
+                                .timestamp_with_time_zone()
+                                .not_null()
+                                .default(now.clone()),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Game::Table)
+                    .add_column(ColumnDef::new(Game::FinishedAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Game::Table)
+                    .add_column(ColumnDef::new(Game::Result).string())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Game::Table)
+                    .add_column(ColumnDef::new(Game::WinnerId).integer())
+                    .to_owned(),
+            )
+            .await?;
+        // Nor can SQLite add a foreign key to a table that already exists
+        if backend != DbBackend::Sqlite {
+            manager
+                .create_foreign_key(
+                    ForeignKey::create()
+                        .name("fk-game-winner-id")
+                        .from(Game::Table, Game::WinnerId)
+                        .to(Player::Table, Player::Id)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DbBackend::Sqlite {
+            manager
+                .drop_foreign_key(
+                    ForeignKey::drop()
+                        .name("fk-game-winner-id")
+                        .table(Game::Table)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        for column in [
+            Game::CreatedAt,
+            Game::UpdatedAt,
+            Game::FinishedAt,
+            Game::Result,
+            Game::WinnerId,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Game::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000002_create_game_table::Game;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Looking up a player's games, as either colour, is done on every connection and for
+/// their statistics.
+const INDEXES: [(&str, Game); 2] = [
+    ("idx-game-player-red-id", Game::PlayerRedId),
+    ("idx-game-player-black-id", Game::PlayerBlackId),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (name, column) in INDEXES {
+            manager
+                .create_index(
+                    Index::create()
+                        .name(name)
+                        .table(Game::Table)
+                        .col(column)
+                        .col(Game::Finished)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (name, _) in INDEXES {
+            manager
+                .drop_index(Index::drop().name(name).table(Game::Table).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000001_create_player_table::Player;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Seek::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Seek::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Seek::PlayerId).integer().not_null())
+                    .col(ColumnDef::new(Seek::Rows).integer().not_null())
+                    .col(ColumnDef::new(Seek::Columns).integer().not_null())
+                    .col(ColumnDef::new(Seek::WinLength).integer().not_null())
+                    .col(ColumnDef::new(Seek::Ruleset).string().not_null())
+                    .col(
+                        ColumnDef::new(Seek::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .default(Expr::current_timestamp())
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-seek-player-id")
+                            .from(Seek::Table, Seek::PlayerId)
+                            .to(Player::Table, Player::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Seek::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Seek {
+    Table,
+    Id,
+    PlayerId,
+    Rows,
+    Columns,
+    WinLength,
+    Ruleset,
+    CreatedAt,
+}
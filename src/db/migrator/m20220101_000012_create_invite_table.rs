@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000001_create_player_table::Player;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Invite::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Invite::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Invite::Code)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Invite::PlayerId).integer().not_null())
+                    .col(ColumnDef::new(Invite::Rows).integer().not_null())
+                    .col(ColumnDef::new(Invite::Columns).integer().not_null())
+                    .col(ColumnDef::new(Invite::WinLength).integer().not_null())
+                    .col(ColumnDef::new(Invite::Ruleset).string().not_null())
+                    .col(
+                        ColumnDef::new(Invite::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .default(Expr::current_timestamp())
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Invite::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-invite-player-id")
+                            .from(Invite::Table, Invite::PlayerId)
+                            .to(Player::Table, Player::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Invite::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Invite {
+    Table,
+    Id,
+    Code,
+    PlayerId,
+    Rows,
+    Columns,
+    WinLength,
+    Ruleset,
+    CreatedAt,
+    ExpiresAt,
+}
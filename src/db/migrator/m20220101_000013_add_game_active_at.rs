@@ -0,0 +1,60 @@
+use chrono::Utc;
+use sea_orm::DbBackend;
+use sea_orm_migration::prelude::*;
+
+use super::m20220101_000002_create_game_table::Game;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX: &str = "idx-game-finished-active-at";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // As with the other times, SQLite stamps the games already there with the time
+        // of the migration, which gives them a full timeout before they're swept
+        let now: SimpleExpr = match manager.get_database_backend() {
+            DbBackend::Sqlite => Utc::now().format("%Y-%m-%d %H:%M:%S").to_string().into(),
+            _ => Expr::current_timestamp().into(),
+        };
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Game::Table)
+                    .add_column(
+                        ColumnDef::new(Game::ActiveAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(now),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // The sweeper looks for unfinished games that have gone quiet
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX)
+                    .table(Game::Table)
+                    .col(Game::Finished)
+                    .col(Game::ActiveAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name(INDEX).table(Game::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Game::Table)
+                    .drop_column(Game::ActiveAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -2,6 +2,18 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_player_table;
 mod m20220101_000002_create_game_table;
+mod m20220101_000003_add_game_dimensions;
+mod m20220101_000004_add_game_ruleset;
+mod m20220101_000005_create_move_table;
+mod m20220101_000006_add_game_version;
+mod m20220101_000007_add_game_status;
+mod m20220101_000008_add_game_takeback;
+mod m20220101_000009_add_game_analysis;
+mod m20220101_000010_add_game_player_indexes;
+mod m20220101_000011_create_seek_table;
+mod m20220101_000012_create_invite_table;
+mod m20220101_000013_add_game_active_at;
+mod m20220101_000014_add_game_draw_offer;
 
 pub struct Migrator;
 
@@ -11,6 +23,18 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_player_table::Migration),
             Box::new(m20220101_000002_create_game_table::Migration),
+            Box::new(m20220101_000003_add_game_dimensions::Migration),
+            Box::new(m20220101_000004_add_game_ruleset::Migration),
+            Box::new(m20220101_000005_create_move_table::Migration),
+            Box::new(m20220101_000006_add_game_version::Migration),
+            Box::new(m20220101_000007_add_game_status::Migration),
+            Box::new(m20220101_000008_add_game_takeback::Migration),
+            Box::new(m20220101_000009_add_game_analysis::Migration),
+            Box::new(m20220101_000010_add_game_player_indexes::Migration),
+            Box::new(m20220101_000011_create_seek_table::Migration),
+            Box::new(m20220101_000012_create_invite_table::Migration),
+            Box::new(m20220101_000013_add_game_active_at::Migration),
+            Box::new(m20220101_000014_add_game_draw_offer::Migration),
         ]
     }
 }
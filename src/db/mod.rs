@@ -1,16 +1,28 @@
 use std::env;
+use std::fmt;
 const DB_NAME: &str = "stacked-fourside";
 
 pub mod entities;
+mod memory;
 mod migrator;
+mod stats;
 
+#[cfg(test)]
+pub use memory::start_game;
+pub use memory::MemoryStore;
+pub use stats::PlayerStats;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::Expr;
 use sea_orm::*;
 use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, DbErr, Statement};
 use sea_orm_migration::{MigratorTrait, SchemaManager};
 
 use entities::{prelude::*, *};
 
-use crate::{game::Square, GAME_SIZE};
+use crate::game::{Board, Colour, Direction, GameResult, GameSettings, Squares};
+use crate::ws::Play;
 
 #[derive(Debug, Clone)]
 pub struct Db {
@@ -19,11 +31,17 @@ pub struct Db {
 }
 
 impl Db {
-    pub async fn db_init() -> Result<Db, DbErr> {
+    pub async fn db_init(reset: bool) -> Result<Db, DbError> {
         let database_url =
             env::var("DATABASE_URL").expect("DATABASE_URL environment variable should be set");
+        Db::connect(&database_url, reset).await
+    }
 
-        let db = Database::connect(&database_url).await?;
+    /// Connects to the database, creating it if need be, and applies any migrations
+    /// that haven't been yet. Only with `reset` is anything already there thrown away,
+    /// by dropping every table and building the schema again from scratch.
+    pub async fn connect(database_url: &str, reset: bool) -> Result<Db, DbError> {
+        let db = Database::connect(database_url).await?;
         let db = match db.get_database_backend() {
             DbBackend::MySql => {
                 db.execute(Statement::from_string(
@@ -36,16 +54,22 @@ impl Db {
                 Database::connect(&url).await?
             }
             DbBackend::Postgres => {
-                db.execute(Statement::from_string(
-                    db.get_database_backend(),
-                    format!("DROP DATABASE IF EXISTS \"{}\";", DB_NAME),
-                ))
-                .await?;
-                db.execute(Statement::from_string(
-                    db.get_database_backend(),
-                    format!("CREATE DATABASE \"{}\";", DB_NAME),
-                ))
-                .await?;
+                // Postgres has no CREATE DATABASE IF NOT EXISTS
+                let exists = db
+                    .query_one(Statement::from_sql_and_values(
+                        db.get_database_backend(),
+                        "SELECT 1 FROM pg_database WHERE datname = $1;",
+                        [DB_NAME.into()],
+                    ))
+                    .await?
+                    .is_some();
+                if !exists {
+                    db.execute(Statement::from_string(
+                        db.get_database_backend(),
+                        format!("CREATE DATABASE \"{}\";", DB_NAME),
+                    ))
+                    .await?;
+                }
 
                 let url = format!("{}/{}", database_url, DB_NAME);
                 Database::connect(&url).await?
@@ -55,56 +79,271 @@ impl Db {
 
         let schema_manager = SchemaManager::new(&db);
 
-        migrator::Migrator::refresh(&db).await?;
+        if reset {
+            println!("Resetting the database, all players and games will be lost");
+            migrator::Migrator::fresh(&db).await?;
+        } else {
+            migrator::Migrator::up(&db, None).await?;
+        }
         // Let's make sure the db is ready
         assert!(schema_manager.has_table("player").await?);
         assert!(schema_manager.has_table("game").await?);
 
         let db = Db {
-            url: database_url.clone(),
+            url: database_url.to_owned(),
             conn: db,
         };
         // Not sure, maybe get rid of this param
         assert!(db.url == database_url);
 
         // Ensure AI players are ID 1 and 2
-        db.get_player(&String::from("AI")).await;
-        db.get_player(&String::from("HAL9000")).await;
+        db.get_player("AI").await?;
+        db.get_player("HAL9000").await?;
 
         Ok(db)
     }
+}
+
+/// Starts a game with an empty board, on the connection or in a transaction.
+async fn insert_game<C: ConnectionTrait>(
+    conn: &C,
+    player_red_id: i32,
+    player_black_id: Option<i32>,
+    settings: &GameSettings,
+    analysis: bool,
+    created_at: DateTime<Utc>,
+) -> Result<game::Model, DbError> {
+    let board = Board::empty(settings);
+    let game = game::ActiveModel {
+        squares: ActiveValue::Set(serde_json::to_value(board.squares()).unwrap()),
+        player_red_id: ActiveValue::Set(Some(player_red_id)),
+        player_black_id: ActiveValue::Set(player_black_id),
+        rows: ActiveValue::Set(settings.rows as i32),
+        columns: ActiveValue::Set(settings.columns as i32),
+        win_length: ActiveValue::Set(settings.win_length as i32),
+        ruleset: ActiveValue::Set(settings.ruleset.as_str().to_owned()),
+        created_at: ActiveValue::Set(created_at.into()),
+        updated_at: ActiveValue::Set(Utc::now().into()),
+        active_at: ActiveValue::Set(Utc::now().into()),
+        analysis: ActiveValue::Set(analysis),
+        ..Default::default()
+    }
+    .insert(conn)
+    .await?;
+    Ok(game)
+}
+
+/// Writes over a game, as `GameStore::save_game` describes.
+async fn update_game<C: ConnectionTrait>(conn: &C, game: game::Model) -> Result<(), DbError> {
+    // Only write over the version this game was read at
+    let saved = Game::update_many()
+        .col_expr(game::Column::Squares, Expr::value(game.squares))
+        .col_expr(game::Column::Finished, Expr::value(game.finished))
+        .col_expr(game::Column::Ply, Expr::value(game.ply))
+        .col_expr(game::Column::Version, Expr::value(game.version + 1))
+        .col_expr(game::Column::UpdatedAt, Expr::value(Utc::now()))
+        .col_expr(game::Column::FinishedAt, Expr::value(game.finished_at))
+        .col_expr(game::Column::Result, Expr::value(game.result))
+        .col_expr(game::Column::WinnerId, Expr::value(game.winner_id))
+        .col_expr(game::Column::TakebackBy, Expr::value(game.takeback_by))
+        .col_expr(game::Column::ActiveAt, Expr::value(game.active_at))
+        .col_expr(
+            game::Column::DrawOfferedBy,
+            Expr::value(game.draw_offered_by),
+        )
+        .filter(game::Column::Id.eq(game.id))
+        .filter(game::Column::Version.eq(game.version))
+        .exec(conn)
+        .await?;
+    match saved.rows_affected {
+        0 => Err(DbError::Conflict(game.id)),
+        _ => Ok(()),
+    }
+}
+
+/// Why the store couldn't do what was asked of it.
+#[derive(Debug)]
+pub enum DbError {
+    /// The database itself failed, or couldn't be reached
+    Db(DbErr),
+    PlayerNotFound(i32),
+    /// A game whose stored squares can't be read back into a board
+    CorruptGame(i32),
+    /// A game that was saved by someone else since it was read
+    Conflict(i32),
+    /// An invite code that another open invite already has
+    CodeTaken(String),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::Db(err) => write!(f, "database error: {err}"),
+            DbError::PlayerNotFound(id) => write!(f, "no player with id {id}"),
+            DbError::CorruptGame(id) => write!(f, "game {id} has unreadable squares"),
+            DbError::Conflict(id) => write!(f, "game {id} was changed by someone else"),
+            DbError::CodeTaken(code) => write!(f, "invite code {code} is already in use"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<DbErr> for DbError {
+    fn from(err: DbErr) -> DbError {
+        DbError::Db(err)
+    }
+}
+
+/// Everything the game needs to keep between moves: players, games and their moves,
+/// and pairing players up into games.
+#[async_trait]
+pub trait GameStore: Send + Sync {
+    /// Finds a player by name, creating them if they're new.
+    async fn get_player(&self, username: &str) -> Result<player::Model, DbError>;
+
+    async fn get_player_by_id(&self, player_id: i32) -> Result<player::Model, DbError>;
+
+    /// Finds a player by name, without creating them.
+    async fn find_player(&self, username: &str) -> Result<Option<player::Model>, DbError>;
+
+    async fn find_game(&self, game_id: i32) -> Result<Option<game::Model>, DbError>;
+
+    /// Every game the player has finished, as either colour, apart from analysis games.
+    async fn get_finished_games(&self, player_id: i32) -> Result<Vec<game::Model>, DbError>;
+
+    /// Every unfinished game, apart from analysis games, that nobody has made a move in
+    /// or asked for a takeback in since `before`.
+    async fn get_inactive_games(&self, before: DateTime<Utc>) -> Result<Vec<game::Model>, DbError>;
+
+    /// Every game the player is in the middle of, oldest first.
+    async fn get_player_games(&self, player_id: i32) -> Result<Vec<game::Model>, DbError>;
+
+    /// Posts an open seek for a game on the given board, taking down any seek the
+    /// player already had.
+    async fn create_seek(
+        &self,
+        player_id: i32,
+        settings: &GameSettings,
+    ) -> Result<seek::Model, DbError>;
+
+    /// Every open seek and who posted it, oldest first.
+    async fn get_seeks(&self) -> Result<Vec<(seek::Model, player::Model)>, DbError>;
+
+    /// The open seek with this id.
+    async fn find_seek(&self, seek_id: i32) -> Result<Option<seek::Model>, DbError>;
+
+    /// Takes down the player's seek. Returns whether they had that seek up.
+    async fn cancel_seek(&self, seek_id: i32, player_id: i32) -> Result<bool, DbError>;
+
+    /// Takes down whatever seek the player has up. Returns whether they had one.
+    async fn cancel_player_seek(&self, player_id: i32) -> Result<bool, DbError>;
+
+    /// Takes down every seek posted before `before`, and returns how many there were.
+    async fn expire_seeks(&self, before: DateTime<Utc>) -> Result<usize, DbError>;
+
+    /// Starts a game on the seek's board between whoever posted it, as red, and the
+    /// player, and takes down both of their seeks. `None` if the seek is already gone.
+    async fn accept_seek(
+        &self,
+        seek_id: i32,
+        player_id: i32,
+    ) -> Result<Option<game::Model>, DbError>;
+
+    /// Opens a private game on the given board that only someone with the code can join,
+    /// until it expires. Invites that have already expired are cleared out.
+    async fn create_invite(
+        &self,
+        player_id: i32,
+        code: &str,
+        settings: &GameSettings,
+        expires_at: DateTime<Utc>,
+    ) -> Result<invite::Model, DbError>;
+
+    /// The invite with this code, unless it has expired.
+    async fn find_invite(&self, code: &str) -> Result<Option<invite::Model>, DbError>;
+
+    /// Starts a game on the invite's board between whoever made it, as red, and the
+    /// player, and takes down both of their seeks. `None` if the invite is already used
+    /// or has expired.
+    async fn join_invite(&self, code: &str, player_id: i32)
+        -> Result<Option<game::Model>, DbError>;
+
+    /// The unfinished game between the two AI players, or a new one.
+    async fn get_ai_game(&self, settings: &GameSettings) -> Result<game::Model, DbError>;
+
+    /// A new game between the two players, started at `created_at`, that's only for
+    /// looking at, so it's never picked for either of them to play in.
+    async fn create_analysis_game(
+        &self,
+        player_red_id: i32,
+        player_black_id: i32,
+        settings: &GameSettings,
+        created_at: DateTime<Utc>,
+    ) -> Result<game::Model, DbError>;
+
+    /// Saves a game's squares, ply, any takeback asked for or draw offered, when it was
+    /// last played in and how it finished, as long as nobody else has saved it since it
+    /// was read. Otherwise it's a `DbError::Conflict`, and nothing changes.
+    async fn save_game(&self, game: game::Model) -> Result<(), DbError>;
+
+    /// Saves a game like `save_game`, and along with it forgets its moves from the
+    /// `taken_back`th on and records the new ones, so that its history always matches
+    /// its board. If anything fails, none of it is saved.
+    async fn save_game_and_moves(
+        &self,
+        game: game::Model,
+        taken_back: Option<usize>,
+        moves: &[NewMove],
+    ) -> Result<(), DbError>;
+
+    /// Every move made in a game, in the order they were played.
+    async fn get_moves(&self, game_id: i32) -> Result<Vec<game_move::Model>, DbError>;
+}
+
+/// A move to record: the ply it was played at, counting from 1, who played it, and what
+/// they played.
+pub type NewMove = (usize, i32, Play);
 
-    pub async fn get_player(&self, username: &String) -> player::Model {
+#[async_trait]
+impl GameStore for Db {
+    async fn get_player(&self, username: &str) -> Result<player::Model, DbError> {
         let player = Player::find()
             .filter(player::Column::Name.eq(username))
             .one(&self.conn)
-            .await
-            .unwrap();
+            .await?;
         match player {
-            Some(player) => player,
+            Some(player) => Ok(player),
             // Create the player if it doesn't exist
-            None => player::ActiveModel {
+            None => Ok(player::ActiveModel {
                 name: ActiveValue::Set(username.to_owned()),
                 ..Default::default()
             }
             .insert(&self.conn)
-            .await
-            .unwrap(),
+            .await?),
         }
     }
 
-    pub async fn get_player_by_id(&self, player_id: i32) -> player::Model {
-        Player::find()
-            .filter(player::Column::Id.eq(player_id))
+    async fn get_player_by_id(&self, player_id: i32) -> Result<player::Model, DbError> {
+        Player::find_by_id(player_id)
             .one(&self.conn)
-            .await
-            .unwrap()
-            .unwrap()
+            .await?
+            .ok_or(DbError::PlayerNotFound(player_id))
     }
 
-    pub async fn get_game(&self, player_id: i32) -> game::Model {
-        // Does a game exist where the player is player red or black? Then return it.
-        let game = Game::find()
+    async fn find_player(&self, username: &str) -> Result<Option<player::Model>, DbError> {
+        Ok(Player::find()
+            .filter(player::Column::Name.eq(username))
+            .one(&self.conn)
+            .await?)
+    }
+
+    async fn find_game(&self, game_id: i32) -> Result<Option<game::Model>, DbError> {
+        Ok(Game::find_by_id(game_id).one(&self.conn).await?)
+    }
+
+    async fn get_finished_games(&self, player_id: i32) -> Result<Vec<game::Model>, DbError> {
+        Ok(Game::find()
             .filter(
                 Condition::all()
                     .add(
@@ -112,75 +351,711 @@ impl Db {
                             .add(game::Column::PlayerRedId.eq(player_id))
                             .add(game::Column::PlayerBlackId.eq(player_id)),
                     )
-                    .add(game::Column::Finished.eq(false)),
+                    .add(game::Column::Finished.eq(true))
+                    .add(game::Column::Analysis.eq(false)),
             )
-            .one(&self.conn)
-            .await
-            .unwrap();
-        if let Some(game) = game {
-            return game;
-        }
+            .order_by_asc(game::Column::Id)
+            .all(&self.conn)
+            .await?)
+    }
 
-        // If not, is there a game waiting for a player? Add this player to player black and return that game.
-        let game = Game::find()
+    async fn get_inactive_games(&self, before: DateTime<Utc>) -> Result<Vec<game::Model>, DbError> {
+        Ok(Game::find()
             .filter(
                 Condition::all()
-                    .add(game::Column::PlayerRedId.is_null().not())
-                    .add(game::Column::PlayerBlackId.is_null()),
+                    .add(game::Column::Finished.eq(false))
+                    .add(game::Column::ActiveAt.lt(before))
+                    .add(game::Column::Analysis.eq(false)),
             )
-            .one(&self.conn)
-            .await
-            .unwrap();
-        if let Some(game) = game {
-            let mut game: game::ActiveModel = game.into();
-            game.player_black_id = ActiveValue::Set(Some(player_id));
-            let game: game::Model = game.update(&self.conn).await.unwrap();
-            return game;
+            .order_by_asc(game::Column::Id)
+            .all(&self.conn)
+            .await?)
+    }
+
+    async fn get_player_games(&self, player_id: i32) -> Result<Vec<game::Model>, DbError> {
+        Ok(Game::find()
+            .filter(
+                Condition::all()
+                    .add(
+                        Condition::any()
+                            .add(game::Column::PlayerRedId.eq(player_id))
+                            .add(game::Column::PlayerBlackId.eq(player_id)),
+                    )
+                    // Games from before the lobby could be left waiting for a second
+                    // player that will never come
+                    .add(game::Column::PlayerBlackId.is_not_null())
+                    .add(game::Column::Finished.eq(false))
+                    .add(game::Column::Analysis.eq(false)),
+            )
+            .order_by_asc(game::Column::Id)
+            .all(&self.conn)
+            .await?)
+    }
+
+    async fn create_seek(
+        &self,
+        player_id: i32,
+        settings: &GameSettings,
+    ) -> Result<seek::Model, DbError> {
+        let txn = self.conn.begin().await?;
+        Seek::delete_many()
+            .filter(seek::Column::PlayerId.eq(player_id))
+            .exec(&txn)
+            .await?;
+        let seek = seek::ActiveModel {
+            player_id: ActiveValue::Set(player_id),
+            rows: ActiveValue::Set(settings.rows as i32),
+            columns: ActiveValue::Set(settings.columns as i32),
+            win_length: ActiveValue::Set(settings.win_length as i32),
+            ruleset: ActiveValue::Set(settings.ruleset.as_str().to_owned()),
+            created_at: ActiveValue::Set(Utc::now().into()),
+            ..Default::default()
         }
+        .insert(&txn)
+        .await?;
+        txn.commit().await?;
+        Ok(seek)
+    }
+
+    async fn get_seeks(&self) -> Result<Vec<(seek::Model, player::Model)>, DbError> {
+        let seeks = Seek::find()
+            .find_also_related(Player)
+            .order_by_asc(seek::Column::Id)
+            .all(&self.conn)
+            .await?;
+        Ok(seeks
+            .into_iter()
+            .filter_map(|(seek, player)| Some((seek, player?)))
+            .collect())
+    }
+
+    async fn find_seek(&self, seek_id: i32) -> Result<Option<seek::Model>, DbError> {
+        Ok(Seek::find_by_id(seek_id).one(&self.conn).await?)
+    }
+
+    async fn cancel_seek(&self, seek_id: i32, player_id: i32) -> Result<bool, DbError> {
+        let deleted = Seek::delete_many()
+            .filter(seek::Column::Id.eq(seek_id))
+            .filter(seek::Column::PlayerId.eq(player_id))
+            .exec(&self.conn)
+            .await?;
+        Ok(deleted.rows_affected > 0)
+    }
+
+    async fn cancel_player_seek(&self, player_id: i32) -> Result<bool, DbError> {
+        let deleted = Seek::delete_many()
+            .filter(seek::Column::PlayerId.eq(player_id))
+            .exec(&self.conn)
+            .await?;
+        Ok(deleted.rows_affected > 0)
+    }
 
-        self.create_empty_game(player_id, None).await
+    async fn expire_seeks(&self, before: DateTime<Utc>) -> Result<usize, DbError> {
+        let deleted = Seek::delete_many()
+            .filter(seek::Column::CreatedAt.lt(before))
+            .exec(&self.conn)
+            .await?;
+        Ok(deleted.rows_affected as usize)
     }
 
-    pub async fn get_ai_game(&self) -> game::Model {
+    async fn accept_seek(
+        &self,
+        seek_id: i32,
+        player_id: i32,
+    ) -> Result<Option<game::Model>, DbError> {
+        let txn = self.conn.begin().await?;
+        let Some(seek) = Seek::find_by_id(seek_id).one(&txn).await? else {
+            return Ok(None);
+        };
+        // Whoever deletes the seek first gets the game
+        let deleted = Seek::delete_by_id(seek.id).exec(&txn).await?;
+        if deleted.rows_affected != 1 {
+            return Ok(None);
+        }
+        Seek::delete_many()
+            .filter(
+                Condition::any()
+                    .add(seek::Column::PlayerId.eq(seek.player_id))
+                    .add(seek::Column::PlayerId.eq(player_id)),
+            )
+            .exec(&txn)
+            .await?;
+        let game = insert_game(
+            &txn,
+            seek.player_id,
+            Some(player_id),
+            &seek.settings(),
+            false,
+            Utc::now(),
+        )
+        .await?;
+        txn.commit().await?;
+        Ok(Some(game))
+    }
+
+    async fn create_invite(
+        &self,
+        player_id: i32,
+        code: &str,
+        settings: &GameSettings,
+        expires_at: DateTime<Utc>,
+    ) -> Result<invite::Model, DbError> {
+        let txn = self.conn.begin().await?;
+        Invite::delete_many()
+            .filter(invite::Column::ExpiresAt.lte(Utc::now()))
+            .exec(&txn)
+            .await?;
+        let invite = invite::ActiveModel {
+            code: ActiveValue::Set(code.to_owned()),
+            player_id: ActiveValue::Set(player_id),
+            rows: ActiveValue::Set(settings.rows as i32),
+            columns: ActiveValue::Set(settings.columns as i32),
+            win_length: ActiveValue::Set(settings.win_length as i32),
+            ruleset: ActiveValue::Set(settings.ruleset.as_str().to_owned()),
+            created_at: ActiveValue::Set(Utc::now().into()),
+            expires_at: ActiveValue::Set(expires_at.into()),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await
+        .map_err(|err| match err.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(_)) => DbError::CodeTaken(code.to_owned()),
+            _ => DbError::Db(err),
+        })?;
+        txn.commit().await?;
+        Ok(invite)
+    }
+
+    async fn find_invite(&self, code: &str) -> Result<Option<invite::Model>, DbError> {
+        Ok(Invite::find()
+            .filter(invite::Column::Code.eq(code))
+            .filter(invite::Column::ExpiresAt.gt(Utc::now()))
+            .one(&self.conn)
+            .await?)
+    }
+
+    async fn join_invite(
+        &self,
+        code: &str,
+        player_id: i32,
+    ) -> Result<Option<game::Model>, DbError> {
+        let txn = self.conn.begin().await?;
+        let Some(invite) = Invite::find()
+            .filter(invite::Column::Code.eq(code))
+            .filter(invite::Column::ExpiresAt.gt(Utc::now()))
+            .one(&txn)
+            .await?
+        else {
+            return Ok(None);
+        };
+        // Whoever deletes the invite first gets the game
+        let deleted = Invite::delete_by_id(invite.id).exec(&txn).await?;
+        if deleted.rows_affected == 0 {
+            return Ok(None);
+        }
+        Seek::delete_many()
+            .filter(
+                Condition::any()
+                    .add(seek::Column::PlayerId.eq(invite.player_id))
+                    .add(seek::Column::PlayerId.eq(player_id)),
+            )
+            .exec(&txn)
+            .await?;
+        let game = insert_game(
+            &txn,
+            invite.player_id,
+            Some(player_id),
+            &invite.settings(),
+            false,
+            Utc::now(),
+        )
+        .await?;
+        txn.commit().await?;
+        Ok(Some(game))
+    }
+
+    async fn get_ai_game(&self, settings: &GameSettings) -> Result<game::Model, DbError> {
         // Does an AI game exist?
         let game = Game::find()
             .filter(
                 Condition::all()
                     .add(game::Column::PlayerRedId.eq(1))
                     .add(game::Column::PlayerBlackId.eq(2))
-                    .add(game::Column::Finished.eq(false)),
+                    .add(game::Column::Finished.eq(false))
+                    .add(game::Column::Analysis.eq(false)),
             )
             .one(&self.conn)
-            .await
-            .unwrap();
+            .await?;
         if let Some(game) = game {
-            return game;
+            return Ok(game);
         }
 
         // Otherwise, create AI game
-        self.create_empty_game(1, Some(2)).await
+        insert_game(&self.conn, 1, Some(2), settings, false, Utc::now()).await
     }
 
-    async fn create_empty_game(&self, player_id: i32, player2_id: Option<i32>) -> game::Model {
-        // Else, start a new game and assign the player to player red.
-        let squares: Vec<Vec<Option<Square>>> = vec![vec![None; GAME_SIZE]; GAME_SIZE];
-        game::ActiveModel {
-            squares: ActiveValue::Set(serde_json::to_value(&squares).unwrap()),
-            player_red_id: ActiveValue::Set(Some(player_id)),
-            player_black_id: ActiveValue::Set(player2_id),
-            ..Default::default()
-        }
-        .insert(&self.conn)
+    async fn create_analysis_game(
+        &self,
+        player_red_id: i32,
+        player_black_id: i32,
+        settings: &GameSettings,
+        created_at: DateTime<Utc>,
+    ) -> Result<game::Model, DbError> {
+        insert_game(
+            &self.conn,
+            player_red_id,
+            Some(player_black_id),
+            settings,
+            true,
+            created_at,
+        )
         .await
-        .unwrap()
     }
 
-    pub async fn save_game(&self, game: game::Model) {
-        let squares = game.squares.clone();
-        let finished = game.finished;
-        let mut game: game::ActiveModel = game.into();
-        game.squares = ActiveValue::Set(squares);
-        game.finished = ActiveValue::Set(finished);
-        game.update(&self.conn).await.unwrap();
+    async fn save_game(&self, game: game::Model) -> Result<(), DbError> {
+        update_game(&self.conn, game).await
+    }
+
+    async fn save_game_and_moves(
+        &self,
+        game: game::Model,
+        taken_back: Option<usize>,
+        moves: &[NewMove],
+    ) -> Result<(), DbError> {
+        let game_id = game.id;
+        let txn = self.conn.begin().await?;
+        update_game(&txn, game).await?;
+        if let Some(ply) = taken_back {
+            Move::delete_many()
+                .filter(game_move::Column::GameId.eq(game_id))
+                .filter(game_move::Column::Ply.gte(ply as i32))
+                .exec(&txn)
+                .await?;
+        }
+        for (ply, player_id, play) in moves {
+            game_move::ActiveModel {
+                game_id: ActiveValue::Set(game_id),
+                ply: ActiveValue::Set(*ply as i32),
+                player_id: ActiveValue::Set(*player_id),
+                row: ActiveValue::Set(play.line as i32),
+                direction: ActiveValue::Set(play.direction.as_str().to_owned()),
+                pop: ActiveValue::Set(play.pop),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn get_moves(&self, game_id: i32) -> Result<Vec<game_move::Model>, DbError> {
+        Ok(Move::find()
+            .filter(game_move::Column::GameId.eq(game_id))
+            .order_by_asc(game_move::Column::Ply)
+            .all(&self.conn)
+            .await?)
+    }
+}
+
+impl game_move::Model {
+    pub fn play(&self) -> Play {
+        Play {
+            line: self.row as usize,
+            // Only ever written from a Direction
+            direction: self.direction.parse().unwrap_or(Direction::Right),
+            pop: self.pop,
+        }
+    }
+}
+
+/// The board a seek, invite or game is stored with.
+fn settings(rows: i32, columns: i32, win_length: i32, ruleset: &str) -> GameSettings {
+    GameSettings {
+        rows: rows as usize,
+        columns: columns as usize,
+        win_length: win_length as usize,
+        // Only ever written from a Ruleset
+        ruleset: ruleset.parse().unwrap_or_default(),
+    }
+}
+
+impl seek::Model {
+    pub fn settings(&self) -> GameSettings {
+        settings(self.rows, self.columns, self.win_length, &self.ruleset)
+    }
+}
+
+impl invite::Model {
+    pub fn settings(&self) -> GameSettings {
+        settings(self.rows, self.columns, self.win_length, &self.ruleset)
+    }
+}
+
+impl game::Model {
+    pub fn settings(&self) -> GameSettings {
+        settings(self.rows, self.columns, self.win_length, &self.ruleset)
+    }
+
+    /// How the game ended, if it has.
+    pub fn result(&self) -> Option<GameResult> {
+        // Only ever written from a GameResult
+        self.result
+            .as_deref()
+            .and_then(|result| result.parse().ok())
+    }
+
+    /// Which colour the player is playing, if they're in this game.
+    pub fn colour_of(&self, player_id: i32) -> Option<Colour> {
+        if self.player_red_id == Some(player_id) {
+            Some(Colour::Red)
+        } else if self.player_black_id == Some(player_id) {
+            Some(Colour::Black)
+        } else {
+            None
+        }
+    }
+
+    /// Ends the game, won by the given colour's player if anyone won it.
+    pub fn finish(&mut self, result: GameResult, winner: Option<Colour>) {
+        self.finished = true;
+        self.finished_at = Some(Utc::now().into());
+        self.result = Some(result.as_str().to_owned());
+        self.winner_id = match winner {
+            Some(Colour::Red) => self.player_red_id,
+            Some(Colour::Black) => self.player_black_id,
+            None => None,
+        };
+        // Nothing is left to answer once it's over
+        self.takeback_by = None;
+        self.draw_offered_by = None;
+    }
+
+    pub fn board(&self) -> Result<Board, DbError> {
+        let squares: Squares = serde_json::from_value(self.squares.clone())
+            .map_err(|_| DbError::CorruptGame(self.id))?;
+        let settings = self.settings();
+        let board = Board::new(squares, settings.win_length, settings.ruleset);
+        // Games from before moves were counted have a ply of 0, and no pops, so the
+        // count of pieces on the board is right for them
+        Ok(match self.ply {
+            0 => board,
+            ply => board.with_ply(ply as usize),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Direction;
+
+    /// Every store should behave the same, so each test runs against all of them.
+    async fn stores() -> Vec<Box<dyn GameStore>> {
+        vec![
+            Box::new(Db::connect("sqlite::memory:", false).await.unwrap()),
+            Box::new(MemoryStore::new()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_players_are_created_once() {
+        for db in stores().await {
+            assert_eq!(db.get_player("AI").await.unwrap().id, 1);
+            assert_eq!(db.get_player("HAL9000").await.unwrap().id, 2);
+
+            let alice = db.get_player("alice").await.unwrap();
+            assert_eq!(db.get_player("alice").await.unwrap(), alice);
+            assert_eq!(db.get_player_by_id(alice.id).await.unwrap(), alice);
+            assert_eq!(db.find_player("alice").await.unwrap(), Some(alice));
+            assert_eq!(db.find_player("nobody").await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accepting_a_seek_starts_a_game() {
+        for db in stores().await {
+            let alice = db.get_player("alice").await.unwrap().id;
+            let bob = db.get_player("bob").await.unwrap().id;
+            let carol = db.get_player("carol").await.unwrap().id;
+            let small = GameSettings {
+                rows: 5,
+                ..GameSettings::default()
+            };
+
+            let first = db
+                .create_seek(alice, &GameSettings::default())
+                .await
+                .unwrap();
+            // A new seek takes the place of the player's old one
+            let seek = db.create_seek(alice, &small).await.unwrap();
+            assert_ne!(seek.id, first.id);
+            let other = db.create_seek(bob, &GameSettings::default()).await.unwrap();
+            let seeks = db.get_seeks().await.unwrap();
+            assert_eq!(
+                seeks
+                    .iter()
+                    .map(|(seek, player)| (seek.id, player.id))
+                    .collect::<Vec<_>>(),
+                [(seek.id, alice), (other.id, bob)]
+            );
+            assert_eq!(seeks[0].0.settings(), small);
+
+            assert_eq!(db.find_seek(other.id).await.unwrap(), Some(other.clone()));
+            assert_eq!(db.find_seek(first.id).await.unwrap(), None);
+            assert!(!db.cancel_seek(other.id, alice).await.unwrap());
+            assert!(db.get_player_games(alice).await.unwrap().is_empty());
+            let game = db.accept_seek(seek.id, carol).await.unwrap().unwrap();
+            assert_eq!(game.player_red_id, Some(alice));
+            assert_eq!(game.player_black_id, Some(carol));
+            assert_eq!(game.board().unwrap().squares().len(), 5);
+            assert_eq!(db.get_player_games(carol).await.unwrap()[0], game);
+            assert!(db.accept_seek(seek.id, bob).await.unwrap().is_none());
+
+            assert!(db.cancel_seek(other.id, bob).await.unwrap());
+            assert!(db.get_seeks().await.unwrap().is_empty());
+
+            // Players can be in any number of games at once
+            let again = db
+                .create_seek(carol, &GameSettings::default())
+                .await
+                .unwrap();
+            let second = db.accept_seek(again.id, bob).await.unwrap().unwrap();
+            let games = db.get_player_games(carol).await.unwrap();
+            assert_eq!(
+                games.iter().map(|game| game.id).collect::<Vec<_>>(),
+                [game.id, second.id]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invites_are_private_and_expire() {
+        for db in stores().await {
+            let alice = db.get_player("alice").await.unwrap().id;
+            let bob = db.get_player("bob").await.unwrap().id;
+            let hour = chrono::Duration::hours(1);
+
+            let invite = db
+                .create_invite(alice, "ABC234", &GameSettings::default(), Utc::now() + hour)
+                .await
+                .unwrap();
+            db.create_invite(alice, "OLD234", &GameSettings::default(), Utc::now() - hour)
+                .await
+                .unwrap();
+            assert_eq!(db.find_invite("OLD234").await.unwrap(), None);
+            assert!(db.join_invite("OLD234", bob).await.unwrap().is_none());
+            assert!(db.get_seeks().await.unwrap().is_empty());
+            assert!(matches!(
+                db.create_invite(bob, "ABC234", &GameSettings::default(), Utc::now() + hour)
+                    .await,
+                Err(DbError::CodeTaken(code)) if code == "ABC234"
+            ));
+            // Only open invites keep their codes
+            db.create_invite(bob, "OLD234", &GameSettings::default(), Utc::now() + hour)
+                .await
+                .unwrap();
+            assert_eq!(db.find_invite("ABC234").await.unwrap(), Some(invite));
+
+            db.create_seek(bob, &GameSettings::default()).await.unwrap();
+            let game = db.join_invite("ABC234", bob).await.unwrap().unwrap();
+            assert_eq!(game.player_red_id, Some(alice));
+            assert_eq!(game.player_black_id, Some(bob));
+            assert!(db.get_seeks().await.unwrap().is_empty());
+            assert!(db.join_invite("ABC234", bob).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_saves_games_and_moves() {
+        for db in stores().await {
+            let mut game = db.get_ai_game(&GameSettings::default()).await.unwrap();
+            let mut board = game.board().unwrap();
+            let plays: Vec<Play> = ["3R", "3L", "2R"]
+                .iter()
+                .map(|play| play.parse().unwrap())
+                .collect();
+            let mut moves = Vec::new();
+            for play in &plays {
+                let colour = board.current_player().unwrap();
+                board.play(colour, play).unwrap();
+                moves.push((board.ply(), 1, *play));
+            }
+            game.squares = serde_json::to_value(board.squares()).unwrap();
+            game.ply = board.ply() as i32;
+            db.save_game_and_moves(game.clone(), None, &moves)
+                .await
+                .unwrap();
+
+            let saved = db.find_game(game.id).await.unwrap().unwrap();
+            assert_eq!(saved.board().unwrap().squares(), board.squares());
+            assert_eq!(saved.board().unwrap().ply(), 3);
+            assert_eq!(
+                db.get_ai_game(&GameSettings::default()).await.unwrap().id,
+                game.id
+            );
+
+            let moves = db.get_moves(game.id).await.unwrap();
+            assert_eq!(moves.iter().map(|m| m.ply).collect::<Vec<_>>(), [1, 2, 3]);
+            assert_eq!(moves.iter().map(|m| m.play()).collect::<Vec<_>>(), plays);
+            assert_eq!(moves[1].play().direction, Direction::Left);
+
+            // A stale save changes neither the game nor its moves
+            let mut taken_back = db.find_game(game.id).await.unwrap().unwrap();
+            taken_back.ply = 1;
+            assert!(matches!(
+                db.save_game_and_moves(game.clone(), Some(2), &[]).await,
+                Err(DbError::Conflict(_))
+            ));
+            assert_eq!(db.get_moves(game.id).await.unwrap(), moves);
+            db.save_game_and_moves(taken_back, Some(2), &[])
+                .await
+                .unwrap();
+            assert_eq!(db.get_moves(game.id).await.unwrap().len(), 1);
+            // Moves played after a takeback get ids of their own
+            let mut replayed = db.find_game(game.id).await.unwrap().unwrap();
+            replayed.ply = 2;
+            db.save_game_and_moves(replayed, None, &[(2, 1, plays[1])])
+                .await
+                .unwrap();
+            let replayed = db.get_moves(game.id).await.unwrap();
+            assert!(replayed[1].id > moves[2].id);
+            assert!(db.find_game(game.id + 1).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refuses_saves_over_newer_versions() {
+        for db in stores().await {
+            let game = db.get_ai_game(&GameSettings::default()).await.unwrap();
+            let mut first = game.clone();
+            first.ply = 1;
+            db.save_game(first).await.unwrap();
+
+            let mut stale = game.clone();
+            stale.ply = 2;
+            assert!(matches!(
+                db.save_game(stale).await,
+                Err(DbError::Conflict(id)) if id == game.id
+            ));
+            let saved = db.find_game(game.id).await.unwrap().unwrap();
+            assert_eq!(saved.ply, 1);
+            assert_eq!(saved.version, game.version + 1);
+
+            let mut fresh = saved.clone();
+            fresh.ply = 2;
+            db.save_game(fresh).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_how_games_end() {
+        for db in stores().await {
+            let mut game = start_game(&*db, "alice", "bob").await;
+            let alice = game.player_red_id.unwrap();
+            let bob = game.player_black_id.unwrap();
+            assert_eq!(game.result(), None);
+            assert_eq!(game.finished_at, None);
+
+            game.draw_offered_by = Some(alice);
+            game.finish(GameResult::BlackWin, Some(Colour::Black));
+            db.save_game(game.clone()).await.unwrap();
+
+            let saved = db.find_game(game.id).await.unwrap().unwrap();
+            assert!(saved.finished);
+            assert_eq!(saved.result(), Some(GameResult::BlackWin));
+            assert_eq!(saved.winner_id, Some(bob));
+            assert!(saved.finished_at.is_some());
+            assert_eq!(saved.draw_offered_by, None);
+            assert!(saved.updated_at >= saved.created_at);
+
+            let finished = db.get_finished_games(alice).await.unwrap();
+            assert_eq!(
+                finished.iter().map(|game| game.id).collect::<Vec<_>>(),
+                [game.id]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analysis_games_are_never_played() {
+        for db in stores().await {
+            let alice = db.get_player("alice").await.unwrap().id;
+            let bob = db.get_player("bob").await.unwrap().id;
+            let analysis = db
+                .create_analysis_game(alice, bob, &GameSettings::default(), Utc::now())
+                .await
+                .unwrap();
+            assert!(analysis.analysis);
+            assert_eq!(analysis.player_black_id, Some(bob));
+
+            assert!(db.get_player_games(alice).await.unwrap().is_empty());
+            let ai = db
+                .create_analysis_game(1, 2, &GameSettings::default(), Utc::now())
+                .await
+                .unwrap();
+            assert_ne!(
+                db.get_ai_game(&GameSettings::default()).await.unwrap().id,
+                ai.id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finds_games_nobody_has_played_in() {
+        for db in stores().await {
+            let game = start_game(&*db, "alice", "bob").await;
+            let (alice, bob) = (game.player_red_id.unwrap(), game.player_black_id.unwrap());
+            db.create_analysis_game(alice, bob, &GameSettings::default(), Utc::now())
+                .await
+                .unwrap();
+
+            let later = Utc::now() + chrono::Duration::minutes(1);
+            assert!(db
+                .get_inactive_games(game.active_at.into())
+                .await
+                .unwrap()
+                .is_empty());
+            let inactive = db.get_inactive_games(later).await.unwrap();
+            assert_eq!(
+                inactive.iter().map(|game| game.id).collect::<Vec<_>>(),
+                [game.id]
+            );
+
+            let mut played = game.clone();
+            played.active_at = later.into();
+            db.save_game(played).await.unwrap();
+            assert!(db.get_inactive_games(later).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_takes_down_old_and_left_seeks() {
+        for db in stores().await {
+            let alice = db.get_player("alice").await.unwrap().id;
+            let bob = db.get_player("bob").await.unwrap().id;
+            let seek = db
+                .create_seek(alice, &GameSettings::default())
+                .await
+                .unwrap();
+            db.create_seek(bob, &GameSettings::default()).await.unwrap();
+
+            assert_eq!(db.expire_seeks(seek.created_at.into()).await.unwrap(), 0);
+            assert!(db.cancel_player_seek(bob).await.unwrap());
+            assert!(!db.cancel_player_seek(bob).await.unwrap());
+            let later = Utc::now() + chrono::Duration::minutes(1);
+            assert_eq!(db.expire_seeks(later).await.unwrap(), 1);
+            assert!(db.get_seeks().await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_player_is_an_error() {
+        for db in stores().await {
+            assert!(matches!(
+                db.get_player_by_id(99).await,
+                Err(DbError::PlayerNotFound(99))
+            ));
+        }
     }
 }
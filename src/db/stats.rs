@@ -0,0 +1,126 @@
+use serde::Serialize;
+
+use super::entities::game;
+use crate::game::{Colour, GameResult};
+
+/// A summary of every game a player has finished.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PlayerStats {
+    pub games: usize,
+    pub wins: usize,
+    pub losses: usize,
+    /// Games that ended without a winner
+    pub draws: usize,
+    pub as_red: ColourStats,
+    pub as_black: ColourStats,
+    /// The most games won in a row
+    pub longest_streak: usize,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ColourStats {
+    pub games: usize,
+    pub wins: usize,
+    /// The fraction of games won, or 0 before any are played
+    pub win_rate: f64,
+}
+
+impl PlayerStats {
+    pub fn from_games(player_id: i32, games: &[game::Model]) -> PlayerStats {
+        let mut games: Vec<&game::Model> = games.iter().collect();
+        // Games from before finishing times were kept come first, in the order they
+        // were started
+        games.sort_by_key(|game| (game.finished_at, game.id));
+
+        let mut stats = PlayerStats::default();
+        let mut streak = 0;
+        for game in games {
+            // Nobody ever sat down against them, so there was no game to speak of
+            if game.player_black_id.is_none() {
+                continue;
+            }
+            let colour = if game.player_red_id == Some(player_id) {
+                Colour::Red
+            } else {
+                Colour::Black
+            };
+            let by_colour = match colour {
+                Colour::Red => &mut stats.as_red,
+                Colour::Black => &mut stats.as_black,
+            };
+            by_colour.games += 1;
+            stats.games += 1;
+            match game.winner_id {
+                Some(winner) if winner == player_id => {
+                    by_colour.wins += 1;
+                    stats.wins += 1;
+                    streak += 1;
+                    stats.longest_streak = stats.longest_streak.max(streak);
+                }
+                Some(_) => {
+                    stats.losses += 1;
+                    streak = 0;
+                }
+                None if game.result() == Some(GameResult::Draw) => {
+                    stats.draws += 1;
+                    streak = 0;
+                }
+                None => (),
+            }
+        }
+        for by_colour in [&mut stats.as_red, &mut stats.as_black] {
+            if by_colour.games > 0 {
+                by_colour.win_rate = by_colour.wins as f64 / by_colour.games as f64;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{start_game, GameStore, MemoryStore};
+
+    #[tokio::test]
+    async fn test_counts_results_and_streaks() {
+        let db = MemoryStore::new();
+        // Alice plays red in the first two games and black in the rest
+        let endings = [
+            ("alice", Some(Colour::Red)),
+            ("alice", Some(Colour::Black)),
+            ("bob", Some(Colour::Black)),
+            ("bob", Some(Colour::Black)),
+            ("bob", None),
+            ("bob", Some(Colour::Black)),
+        ];
+        for (red, winner) in endings {
+            let black = if red == "alice" { "bob" } else { "alice" };
+            let mut game = start_game(&db, red, black).await;
+            game.finish(GameResult::from_winner(winner), winner);
+            db.save_game(game).await.unwrap();
+        }
+        let alice = db.get_player("alice").await.unwrap().id;
+        let mut games = db.get_finished_games(alice).await.unwrap();
+
+        // A game nobody joined doesn't count, and one left without a winner isn't a draw
+        let mut unjoined = games[0].clone();
+        unjoined.player_black_id = None;
+        unjoined.finish(GameResult::Abandoned, None);
+        let mut abandoned = games[0].clone();
+        abandoned.finish(GameResult::Abandoned, None);
+        games.extend([unjoined, abandoned]);
+
+        let stats = PlayerStats::from_games(alice, &games);
+        assert_eq!(
+            (stats.games, stats.wins, stats.losses, stats.draws),
+            (7, 4, 1, 1)
+        );
+        assert_eq!((stats.as_red.games, stats.as_red.wins), (3, 1));
+        assert_eq!(stats.as_red.win_rate, 1.0 / 3.0);
+        assert_eq!((stats.as_black.games, stats.as_black.wins), (4, 3));
+        assert_eq!(stats.longest_streak, 2);
+
+        assert_eq!(PlayerStats::from_games(alice, &[]), PlayerStats::default());
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use super::bitboard::Position;
+use super::{solver, Board, Colour};
+use crate::ws::Play;
+
+/// Larger than any score the evaluation heuristic can give a position that hasn't been
+/// won yet.
+pub(super) const WIN_SCORE: i32 = 1_000_000;
+
+/// A way of picking moves for a computer player.
+pub trait Strategy {
+    /// Picks a move for whoever's turn it is, or `None` if the game is over.
+    fn choose_move(&self, board: &Board) -> Option<Play>;
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Beginner,
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn strategy(self) -> Box<dyn Strategy + Send> {
+        match self {
+            Difficulty::Beginner => Box::new(Random),
+            Difficulty::Easy => Box::new(AlphaBeta::new(1)),
+            Difficulty::Medium => Box::new(AlphaBeta::new(3)),
+            Difficulty::Hard => Box::new(AlphaBeta::new(7)),
+        }
+    }
+}
+
+/// Plays any legal move at all.
+pub struct Random;
+
+impl Strategy for Random {
+    fn choose_move(&self, board: &Board) -> Option<Play> {
+        board.current_player()?;
+        board.legal_moves().choose(&mut rand::thread_rng()).copied()
+    }
+}
+
+/// How long the computer may think about a move before playing the best it's found, so
+/// that big boards don't keep the player waiting.
+pub const THINKING_TIME: Duration = Duration::from_secs(2);
+
+/// Looks ahead up to a number of moves with negamax and alpha-beta pruning, searching
+/// deeper only while there's time left, and falls back to a heuristic for the positions
+/// it can't see past. Ties between equally good moves are broken at random so that games
+/// don't all go the same way.
+pub struct AlphaBeta {
+    pub depth: usize,
+    /// How long to search for, though the shallowest search always finishes
+    pub budget: Duration,
+}
+
+impl AlphaBeta {
+    /// Searches up to `depth` moves ahead, for no longer than `THINKING_TIME`.
+    pub fn new(depth: usize) -> AlphaBeta {
+        AlphaBeta {
+            depth,
+            budget: THINKING_TIME,
+        }
+    }
+}
+
+impl Strategy for AlphaBeta {
+    fn choose_move(&self, board: &Board) -> Option<Play> {
+        let analysis = solver::deepen(board, self.depth, self.budget);
+        let best_plays: Vec<Play> = analysis
+            .moves
+            .iter()
+            .filter(|score| score.score == analysis.evaluation)
+            .map(|score| score.play)
+            .collect();
+        best_plays.choose(&mut rand::thread_rng()).copied()
+    }
+}
+
+pub(super) struct Search {
+    /// Every line on the board that could be a win, for the heuristic
+    pub(super) lines: Vec<u128>,
+}
+
+impl Search {
+    /// Scores a position for the given colour by counting the lines that each side
+    /// could still complete, weighing more heavily the ones that are closer to done.
+    pub(super) fn evaluate(&self, position: &Position, colour: Colour) -> i32 {
+        let ours = position.mask(colour);
+        let theirs = position.mask(colour.other());
+
+        let mut score = 0;
+        for line in &self.lines {
+            match ((ours & line).count_ones(), (theirs & line).count_ones()) {
+                (0, 0) => (),
+                (ours, 0) => score += 1 << (2 * ours),
+                (0, theirs) => score -= 1 << (2 * theirs),
+                _ => (),
+            }
+        }
+        score
+    }
+}
+
+/// Moves in the middle rows and columns tend to be stronger, so trying them first
+/// lets alpha-beta prune more.
+pub(super) fn ordered_moves(position: &Position) -> Vec<Play> {
+    let mut plays = position.legal_moves();
+    plays.sort_by_key(|play| {
+        let lines = if play.direction.is_vertical() {
+            position.columns()
+        } else {
+            position.rows()
+        };
+        play.line.abs_diff(lines / 2)
+    });
+    plays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::notation::Moves;
+    use crate::game::rules::Ruleset;
+    use crate::game::{Direction, GameSettings, MAX_BOARD_SIZE};
+    use std::time::Instant;
+
+    fn play_all(board: &mut Board, moves: &str) {
+        let moves: Moves = moves.parse().unwrap();
+        for play in &moves.0 {
+            let colour = board.current_player().unwrap();
+            board.play(colour, play).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_takes_the_win() {
+        let mut board = Board::empty(&GameSettings::default());
+        play_all(&mut board, "0R 6R 0R 6R 0R 5L");
+
+        for depth in 1..=3 {
+            let play = AlphaBeta::new(depth).choose_move(&board).unwrap();
+            assert_eq!(play.line, 0, "depth {depth}");
+            assert_eq!(play.direction, Direction::Right, "depth {depth}");
+        }
+    }
+
+    #[test]
+    fn test_blocks_the_loss() {
+        let mut board = Board::empty(&GameSettings::default());
+        play_all(&mut board, "3L 0R 3L 6R 3L");
+
+        let play = AlphaBeta::new(2).choose_move(&board).unwrap();
+        assert_eq!(play.line, 3);
+        assert_eq!(play.direction, Direction::Left);
+    }
+
+    #[test]
+    fn test_thinks_in_time_on_the_largest_boards() {
+        for ruleset in [Ruleset::FourSided, Ruleset::PopOut] {
+            let board = Board::empty(&GameSettings {
+                rows: MAX_BOARD_SIZE,
+                columns: MAX_BOARD_SIZE,
+                ruleset,
+                ..GameSettings::default()
+            });
+            let started = Instant::now();
+            assert!(Difficulty::Hard.strategy().choose_move(&board).is_some());
+            // Time runs out between checks of the clock, so it can overrun a little
+            let took = started.elapsed();
+            assert!(
+                took < THINKING_TIME + Duration::from_secs(1),
+                "{ruleset:?} took {took:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_no_move_when_over() {
+        let mut board = Board::empty(&GameSettings::default());
+        play_all(&mut board, "0R 6R 0R 6R 0R 5L 0R");
+
+        assert!(Random.choose_move(&board).is_none());
+        assert!(AlphaBeta::new(3).choose_move(&board).is_none());
+    }
+}
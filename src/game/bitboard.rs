@@ -0,0 +1,544 @@
+use super::rules::Ruleset;
+use super::{Board, Colour, Direction, MoveError, MoveOutcome, Square, Squares, MAX_BOARD_SIZE};
+use crate::ws::Play;
+
+/// A compact copy of a board for searching through many positions quickly.
+///
+/// Each colour gets one bit per square, at `row * (columns + 1) + column`. The extra
+/// column at the end of every row always stays empty, so that shifting a line of
+/// pieces along a row or diagonal can never wrap around into the next row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    rows: usize,
+    columns: usize,
+    win_length: usize,
+    ruleset: Ruleset,
+    /// How many moves have been made, which decides whose turn it is
+    ply: usize,
+    red: u128,
+    black: u128,
+    /// Pieces that came in from the right, moving left, so squares can be rebuilt
+    from_left: u128,
+    /// Pieces that came in from the top, moving down
+    from_top: u128,
+    /// Pieces that came in from the bottom, moving up
+    from_bottom: u128,
+    /// For each row, the next square filled by a `Right` play and by a `Left` play
+    fill: [(u8, u8); MAX_BOARD_SIZE],
+    /// For each column, the next square filled by a `Down` play and by an `Up` play
+    column_fill: [(u8, u8); MAX_BOARD_SIZE],
+}
+
+impl Position {
+    pub fn from_board(board: &Board) -> Position {
+        Position {
+            ruleset: board.ruleset(),
+            ply: board.ply(),
+            ..Position::from_squares(board.squares(), board.win_length())
+        }
+    }
+
+    /// Makes a stacked position, assuming one move was made for every piece.
+    pub fn from_squares(squares: &Squares, win_length: usize) -> Position {
+        let rows = squares.len();
+        let columns = squares.first().map_or(0, |row| row.len());
+        let mut position = Position {
+            rows,
+            columns,
+            win_length,
+            ruleset: Ruleset::Stacked,
+            ply: squares.iter().flatten().flatten().count(),
+            red: 0,
+            black: 0,
+            from_left: 0,
+            from_top: 0,
+            from_bottom: 0,
+            fill: [(0, 0); MAX_BOARD_SIZE],
+            column_fill: [(0, 0); MAX_BOARD_SIZE],
+        };
+
+        for (row, squares) in squares.iter().enumerate() {
+            for (column, square) in squares.iter().enumerate() {
+                if let Some(square) = square {
+                    let bit = position.bit(row, column);
+                    match square.value {
+                        Colour::Red => position.red |= bit,
+                        Colour::Black => position.black |= bit,
+                    }
+                    position.mark_direction(bit, square.direction);
+                }
+            }
+            position.update_fill(row);
+        }
+        for column in 0..columns {
+            position.update_column_fill(column);
+        }
+        position
+    }
+
+    /// Rebuilds the squares that this position was made from, or has been played to.
+    pub fn squares(&self) -> Squares {
+        (0..self.rows)
+            .map(|row| {
+                (0..self.columns)
+                    .map(|column| {
+                        let bit = self.bit(row, column);
+                        let value = if self.red & bit != 0 {
+                            Colour::Red
+                        } else if self.black & bit != 0 {
+                            Colour::Black
+                        } else {
+                            return None;
+                        };
+                        let direction = if self.from_left & bit != 0 {
+                            Direction::Left
+                        } else if self.from_top & bit != 0 {
+                            Direction::Down
+                        } else if self.from_bottom & bit != 0 {
+                            Direction::Up
+                        } else {
+                            Direction::Right
+                        };
+                        Some(Square { value, direction })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn mask(&self, colour: Colour) -> u128 {
+        match colour {
+            Colour::Red => self.red,
+            Colour::Black => self.black,
+        }
+    }
+
+    fn bit(&self, row: usize, column: usize) -> u128 {
+        1 << (row * (self.columns + 1) + column)
+    }
+
+    fn row_mask(&self) -> u128 {
+        (1 << self.columns) - 1
+    }
+
+    fn occupied_row(&self, row: usize) -> u128 {
+        ((self.red | self.black) >> (row * (self.columns + 1))) & self.row_mask()
+    }
+
+    /// The occupied squares of a column, one bit per row.
+    fn occupied_column(&self, column: usize) -> u128 {
+        let occupied = self.red | self.black;
+        (0..self.rows).fold(0, |bits, row| {
+            bits | (((occupied & self.bit(row, column)) != 0) as u128) << row
+        })
+    }
+
+    fn mark_direction(&mut self, bit: u128, direction: Direction) {
+        match direction {
+            Direction::Right => (),
+            Direction::Left => self.from_left |= bit,
+            Direction::Down => self.from_top |= bit,
+            Direction::Up => self.from_bottom |= bit,
+        }
+    }
+
+    /// Whether the square at the given end of a row, as `Board` pops it, holds one of
+    /// the colour's pieces.
+    fn can_pop(&self, colour: Colour, row: usize, direction: Direction) -> bool {
+        let column = match direction {
+            Direction::Right => 0,
+            Direction::Left => self.columns - 1,
+            Direction::Up | Direction::Down => return false,
+        };
+        self.mask(colour) & self.bit(row, column) != 0
+    }
+
+    fn update_fill(&mut self, row: usize) {
+        self.fill[row] = fill(self.occupied_row(row), self.columns);
+    }
+
+    fn update_column_fill(&mut self, column: usize) {
+        self.column_fill[column] = fill(self.occupied_column(column), self.rows);
+    }
+
+    fn side_to_move(&self) -> Colour {
+        match self.ply % 2 {
+            0 => Colour::Red,
+            _ => Colour::Black,
+        }
+    }
+
+    /// Whether whoever's turn it is has any move at all, without listing them.
+    fn has_moves(&self) -> bool {
+        let colour = self.side_to_move();
+        (0..self.rows).any(|row| {
+            self.occupied_row(row) != self.row_mask()
+                || (self.ruleset == Ruleset::PopOut
+                    && (self.can_pop(colour, row, Direction::Right)
+                        || self.can_pop(colour, row, Direction::Left)))
+        })
+    }
+
+    /// Whether the given pieces have `win_length` of them in a row anywhere.
+    pub fn has_line(&self, pieces: u128) -> bool {
+        let columns = self.columns as u32;
+        // horizontal, vertical, diagonal and anti-diagonal
+        [1, columns + 1, columns + 2, columns].iter().any(|&shift| {
+            let mut line = pieces;
+            for k in 1..self.win_length as u32 {
+                line &= pieces >> (shift * k);
+            }
+            line != 0
+        })
+    }
+
+    /// The same winner as `Board::winner`: when both colours have a line, which only
+    /// a pop can cause, whoever moved last wins.
+    pub fn winner(&self) -> Option<Colour> {
+        let last_mover = self.side_to_move().other();
+        [last_mover, last_mover.other()]
+            .into_iter()
+            .find(|&colour| self.has_line(self.mask(colour)))
+    }
+
+    /// Whose turn it is, or `None` if the game is over.
+    pub fn current_player(&self) -> Option<Colour> {
+        if self.winner().is_some() || !self.has_moves() {
+            None
+        } else {
+            Some(self.side_to_move())
+        }
+    }
+
+    /// The same moves as `Board::legal_moves`, in the same order.
+    pub fn legal_moves(&self) -> Vec<Play> {
+        let colour = self.side_to_move();
+        let mut plays = Vec::new();
+        for (row, &(from_start, from_end)) in self.fill[..self.rows].iter().enumerate() {
+            if (from_start as usize) < self.columns {
+                plays.push(Play {
+                    line: row,
+                    direction: Direction::Right,
+                    pop: false,
+                });
+            }
+            if self.ruleset != Ruleset::Classic && from_start < from_end {
+                plays.push(Play {
+                    line: row,
+                    direction: Direction::Left,
+                    pop: false,
+                });
+            }
+            if self.ruleset == Ruleset::PopOut {
+                for direction in [Direction::Right, Direction::Left] {
+                    if self.can_pop(colour, row, direction) {
+                        plays.push(Play {
+                            line: row,
+                            direction,
+                            pop: true,
+                        });
+                    }
+                }
+            }
+        }
+        if self.ruleset == Ruleset::FourSided {
+            // Squares a row's placement already lands on aren't offered again
+            let lands_on = |row: u8, column: usize| {
+                let (from_start, from_end) = self.fill[row as usize];
+                column == from_start as usize || column == from_end as usize
+            };
+            for (column, &(from_top, from_bottom)) in
+                self.column_fill[..self.columns].iter().enumerate()
+            {
+                if (from_top as usize) < self.rows && !lands_on(from_top, column) {
+                    plays.push(Play {
+                        line: column,
+                        direction: Direction::Down,
+                        pop: false,
+                    });
+                }
+                if from_top < from_bottom && !lands_on(from_bottom, column) {
+                    plays.push(Play {
+                        line: column,
+                        direction: Direction::Up,
+                        pop: false,
+                    });
+                }
+            }
+        }
+        plays
+    }
+
+    /// Makes a move for the given colour, with the same checks as `Board::play`.
+    pub fn play(&mut self, colour: Colour, play: &Play) -> Result<MoveOutcome, MoveError> {
+        if self.current_player().ok_or(MoveError::GameOver)? != colour {
+            return Err(MoveError::NotYourTurn);
+        }
+        let illegal_direction = match self.ruleset {
+            Ruleset::PopOut if play.direction.is_vertical() => true,
+            Ruleset::PopOut => false,
+            _ if play.pop => return Err(MoveError::PopNotAllowed),
+            Ruleset::Stacked => play.direction.is_vertical(),
+            Ruleset::Classic => play.direction != Direction::Right,
+            Ruleset::FourSided => false,
+        };
+        if illegal_direction {
+            return Err(MoveError::IllegalDirection(play.direction));
+        }
+        if play.direction.is_vertical() {
+            if play.line >= self.columns {
+                return Err(MoveError::ColumnOutOfRange(play.line));
+            }
+        } else if play.line >= self.rows {
+            return Err(MoveError::RowOutOfRange(play.line));
+        }
+        if play.pop {
+            self.pop(colour, play)?;
+        } else {
+            self.place(colour, play)?;
+        }
+        self.ply += 1;
+
+        Ok(match (self.winner(), self.current_player()) {
+            (Some(winner), _) => MoveOutcome::Win(winner),
+            (None, Some(next)) => MoveOutcome::Continue(next),
+            (None, None) => MoveOutcome::Draw,
+        })
+    }
+
+    fn place(&mut self, colour: Colour, play: &Play) -> Result<(), MoveError> {
+        let (from_start, from_end) = self.fill.get(play.line).copied().unwrap_or_default();
+        let (from_top, from_bottom) = self.column_fill.get(play.line).copied().unwrap_or_default();
+        let (row, column) = match play.direction {
+            Direction::Right => (play.line, from_start as usize),
+            Direction::Left => (play.line, from_end as usize),
+            Direction::Down => (from_top as usize, play.line),
+            Direction::Up => (from_bottom as usize, play.line),
+        };
+        if column >= self.columns {
+            return Err(MoveError::RowFull(play.line));
+        }
+        if row >= self.rows {
+            return Err(MoveError::ColumnFull(play.line));
+        }
+
+        let bit = self.bit(row, column);
+        match colour {
+            Colour::Red => self.red |= bit,
+            Colour::Black => self.black |= bit,
+        }
+        self.mark_direction(bit, play.direction);
+        self.update_fill(row);
+        self.update_column_fill(column);
+        Ok(())
+    }
+
+    /// Pops the end piece and slides the run of pieces stacked against that end
+    /// along by one, the same way `Board` does.
+    fn pop(&mut self, colour: Colour, play: &Play) -> Result<(), MoveError> {
+        if !self.can_pop(colour, play.line, play.direction) {
+            return Err(MoveError::CannotPop(play.line));
+        }
+
+        let offset = play.line * (self.columns + 1);
+        let (from_start, from_end) = self.fill[play.line];
+        let full = self.occupied_row(play.line) == self.row_mask();
+        let run = match play.direction {
+            Direction::Right => (1 << from_start) - 1,
+            Direction::Left if full => self.row_mask(),
+            Direction::Left => self.row_mask() & !((2 << from_end) - 1),
+            Direction::Up | Direction::Down => 0,
+        } << offset;
+        // The popped piece is shifted out of the run, into the next row's padding or
+        // this row's, and masked away
+        let slide = |pieces: u128| match play.direction {
+            Direction::Right => (pieces & !run) | ((pieces & run) >> 1 & run),
+            _ => (pieces & !run) | ((pieces & run) << 1 & run),
+        };
+        self.red = slide(self.red);
+        self.black = slide(self.black);
+        self.from_left = slide(self.from_left);
+        self.from_top = slide(self.from_top);
+        self.from_bottom = slide(self.from_bottom);
+        self.update_fill(play.line);
+        for column in 0..self.columns {
+            self.update_column_fill(column);
+        }
+        Ok(())
+    }
+
+    /// Every line of `win_length` squares on the board, as masks.
+    pub fn lines(&self) -> Vec<u128> {
+        let rows = self.rows as isize;
+        let columns = self.columns as isize;
+        let reach = self.win_length as isize - 1;
+
+        let mut lines = Vec::new();
+        for (dx, dy) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for i in 0..rows {
+                for j in 0..columns {
+                    let (end_i, end_j) = (i + reach * dx, j + reach * dy);
+                    if end_i >= rows || end_j < 0 || end_j >= columns {
+                        continue;
+                    }
+                    lines.push((0..=reach).fold(0, |line, k| {
+                        line | self.bit((i + k * dx) as usize, (j + k * dy) as usize)
+                    }));
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// Works out where the next piece from either end of a row or column of `length`
+/// squares lands, which for a full one is past its end.
+fn fill(occupied: u128, length: usize) -> (u8, u8) {
+    let from_start = occupied.trailing_ones() as u8;
+    let from_end = if occupied == (1 << length) - 1 {
+        length as u8
+    } else {
+        (length - 1 - (occupied << (128 - length)).leading_ones() as usize) as u8
+    };
+    (from_start, from_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{calculate_winner, GameSettings};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_squares_round_trip() {
+        let u: Option<Square> = None;
+        let r = Some(Square {
+            value: Colour::Red,
+            direction: Direction::Right,
+        });
+        let b = Some(Square {
+            value: Colour::Black,
+            direction: Direction::Left,
+        });
+        let d = Some(Square {
+            value: Colour::Black,
+            direction: Direction::Down,
+        });
+        let p = Some(Square {
+            value: Colour::Red,
+            direction: Direction::Up,
+        });
+        let squares: Squares = vec![
+            vec![r, r, u, u, u, b],
+            vec![u, d, u, u, p, u],
+            vec![r, b, r, b, r, b],
+            vec![b, u, r, u, u, u],
+        ];
+
+        let position = Position::from_squares(&squares, 4);
+        assert_eq!(position.squares(), squares);
+        assert_eq!(position.ply, 13);
+        assert_eq!(position.fill[0], (2, 4));
+        assert_eq!(position.fill[1], (0, 5));
+        assert_eq!(position.column_fill[1], (3, 3));
+        assert_eq!(position.column_fill[3], (0, 3));
+        assert_eq!(position.fill[2], (6, 6));
+        assert_eq!(position.fill[3], (1, 5));
+    }
+
+    #[test]
+    fn test_matches_calculate_winner() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..2000 {
+            let rows = rng.gen_range(4..=MAX_BOARD_SIZE);
+            let columns = rng.gen_range(4..=MAX_BOARD_SIZE);
+            let win_length = rng.gen_range(3..=rows.max(columns));
+            let squares: Squares = (0..rows)
+                .map(|_| {
+                    (0..columns)
+                        .map(|_| match rng.gen_range(0..10) {
+                            0..=3 => None,
+                            4..=6 => Some(Square {
+                                value: Colour::Red,
+                                direction: Direction::Right,
+                            }),
+                            _ => Some(Square {
+                                value: Colour::Black,
+                                direction: Direction::Left,
+                            }),
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let position = Position::from_squares(&squares, win_length);
+            let expected = calculate_winner(&squares, win_length);
+            match (
+                position.has_line(position.red),
+                position.has_line(position.black),
+            ) {
+                // When both colours have a line, which one is found first is arbitrary
+                (true, true) => assert!(expected.is_some()),
+                _ => assert_eq!(position.winner(), expected),
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_board_moves() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for game in 0..400 {
+            let rows = rng.gen_range(4..=MAX_BOARD_SIZE);
+            let columns = rng.gen_range(4..=MAX_BOARD_SIZE);
+            let settings = GameSettings {
+                rows,
+                columns,
+                win_length: rng.gen_range(3..=rows.max(columns).min(6)),
+                ruleset: [
+                    Ruleset::Stacked,
+                    Ruleset::Classic,
+                    Ruleset::PopOut,
+                    Ruleset::FourSided,
+                ][game % 4],
+            };
+            let mut board = Board::empty(&settings);
+            let mut position = Position::from_board(&board);
+            let mut played = Vec::new();
+
+            // Pop-out games can go on forever
+            for _ in 0..500 {
+                let Some(colour) = board.current_player() else {
+                    break;
+                };
+                assert_eq!(position.current_player(), Some(colour));
+                let plays = board.legal_moves();
+                assert_eq!(position.legal_moves(), plays);
+
+                let play = plays.choose(&mut rng).unwrap();
+                assert_eq!(position.play(colour, play), board.play(colour, play));
+                assert_eq!(&position.squares(), board.squares());
+                assert_eq!(position, Position::from_board(&board));
+                played.push(*play);
+            }
+            assert_eq!(position.current_player(), board.current_player());
+            assert_eq!(position.winner(), board.winner());
+            let replayed = Board::from_moves(&settings, &played).unwrap();
+            assert_eq!(replayed.squares(), board.squares());
+            assert_eq!(replayed.ply(), board.ply());
+        }
+    }
+}
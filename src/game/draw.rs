@@ -0,0 +1,86 @@
+//! Ending a game as a draw by agreement. A player offers a draw, and the other player
+//! accepts or declines. An offer stands until it's answered or either player moves.
+
+use serde::Deserialize;
+
+use super::MoveError;
+use crate::db::entities::game;
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Draw {
+    Offer,
+    Accept,
+    Decline,
+}
+
+/// Records a draw being offered or answered. Returns whether the players have agreed to
+/// one, which ends the game.
+pub(super) fn answer_draw(
+    game: &mut game::Model,
+    player_id: i32,
+    draw: Draw,
+    is_ai_game: bool,
+) -> Result<bool, MoveError> {
+    match draw {
+        // The AI always plays on
+        Draw::Offer if is_ai_game => Err(MoveError::DrawDeclined),
+        Draw::Offer => {
+            game.draw_offered_by = Some(player_id);
+            Ok(false)
+        }
+        Draw::Accept | Draw::Decline => match game.draw_offered_by {
+            Some(offerer) if offerer != player_id => {
+                game.draw_offered_by = None;
+                Ok(draw == Draw::Accept)
+            }
+            _ => Err(MoveError::NoDrawToAnswer),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{start_game, MemoryStore};
+
+    async fn new_game() -> game::Model {
+        start_game(&MemoryStore::new(), "alice", "bob").await
+    }
+
+    #[tokio::test]
+    async fn test_draws_by_agreement() {
+        let mut game = new_game().await;
+        let alice = game.player_red_id.unwrap();
+        let bob = game.player_black_id.unwrap();
+
+        assert_eq!(
+            answer_draw(&mut game, bob, Draw::Accept, false),
+            Err(MoveError::NoDrawToAnswer)
+        );
+        assert_eq!(answer_draw(&mut game, alice, Draw::Offer, false), Ok(false));
+        assert_eq!(game.draw_offered_by, Some(alice));
+        assert_eq!(
+            answer_draw(&mut game, alice, Draw::Accept, false),
+            Err(MoveError::NoDrawToAnswer)
+        );
+        assert_eq!(answer_draw(&mut game, bob, Draw::Decline, false), Ok(false));
+        assert_eq!(game.draw_offered_by, None);
+
+        answer_draw(&mut game, bob, Draw::Offer, false).unwrap();
+        assert_eq!(answer_draw(&mut game, alice, Draw::Accept, false), Ok(true));
+        assert_eq!(game.draw_offered_by, None);
+    }
+
+    #[tokio::test]
+    async fn test_ai_declines_draws() {
+        let mut game = new_game().await;
+        let alice = game.player_red_id.unwrap();
+
+        assert_eq!(
+            answer_draw(&mut game, alice, Draw::Offer, true),
+            Err(MoveError::DrawDeclined)
+        );
+        assert_eq!(game.draw_offered_by, None);
+    }
+}
@@ -0,0 +1,1444 @@
+use std::fmt;
+use std::str::FromStr;
+
+pub mod ai;
+pub mod bitboard;
+pub mod draw;
+pub mod notation;
+pub mod record;
+pub mod rules;
+pub mod solver;
+pub mod takeback;
+
+use crate::db::entities::game;
+use crate::db::DbError;
+use crate::ws::Play;
+use crate::{lobby, Client, Clients, Db, Sockets};
+use bitboard::Position;
+use chrono::Utc;
+use draw::{answer_draw, Draw};
+use rules::Ruleset;
+use serde::{Deserialize, Serialize};
+use takeback::{answer_takeback, Takeback};
+use warp::ws::Message;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Colour {
+    Red,
+    Black,
+}
+
+impl Colour {
+    pub fn other(self) -> Colour {
+        match self {
+            Colour::Red => Colour::Black,
+            Colour::Black => Colour::Red,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Colour::Red => "red",
+            Colour::Black => "black",
+        }
+    }
+}
+
+impl FromStr for Colour {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Colour, String> {
+        match s {
+            "red" => Ok(Colour::Red),
+            "black" => Ok(Colour::Black),
+            _ => Err(format!("unknown colour {s}")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Left,
+    /// Games from before win lines were sent separately marked their winning squares
+    /// as "win" in place of the direction, which is lost, so they're read as `Right`
+    #[serde(alias = "win")]
+    Right,
+    /// Enters a column from the bottom
+    Up,
+    /// Enters a column from the top
+    Down,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::Up => "up",
+            Direction::Down => "down",
+        }
+    }
+
+    /// Whether the piece enters a column rather than a row.
+    pub fn is_vertical(self) -> bool {
+        matches!(self, Direction::Up | Direction::Down)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Square {
+    value: Colour,
+    direction: Direction,
+}
+
+pub type Squares = Vec<Vec<Option<Square>>>;
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Direction, String> {
+        match s {
+            "left" => Ok(Direction::Left),
+            "right" => Ok(Direction::Right),
+            "up" => Ok(Direction::Up),
+            "down" => Ok(Direction::Down),
+            _ => Err(format!("unknown direction {s}")),
+        }
+    }
+}
+
+/// A run of one colour's pieces long enough to win.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct WinLine {
+    pub colour: Colour,
+    /// The (row, column) of every square in the line, in order
+    pub squares: Vec<(usize, usize)>,
+}
+
+pub const MIN_BOARD_SIZE: usize = 4;
+pub const MAX_BOARD_SIZE: usize = 10;
+pub const MIN_WIN_LENGTH: usize = 3;
+
+/// The shape of a board, how many pieces in a row it takes to win and which rules
+/// are played, chosen when a game is created.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(default)]
+pub struct GameSettings {
+    pub rows: usize,
+    pub columns: usize,
+    pub win_length: usize,
+    pub ruleset: Ruleset,
+}
+
+impl Default for GameSettings {
+    fn default() -> GameSettings {
+        GameSettings {
+            rows: 7,
+            columns: 7,
+            win_length: 4,
+            ruleset: Ruleset::default(),
+        }
+    }
+}
+
+impl GameSettings {
+    /// Boards can be rectangular, but must fit within the size limits and have a win
+    /// length that fits on the board.
+    pub fn is_valid(&self) -> bool {
+        let sizes = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+        sizes.contains(&self.rows)
+            && sizes.contains(&self.columns)
+            && (MIN_WIN_LENGTH..=self.rows.max(self.columns)).contains(&self.win_length)
+    }
+}
+
+/// The result of successfully applying a move to a board.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MoveOutcome {
+    /// The game goes on, and it's now this colour's turn
+    Continue(Colour),
+    Win(Colour),
+    Draw,
+}
+
+/// How a game ended.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    RedWin,
+    BlackWin,
+    Draw,
+    Resigned,
+    /// A player left the game and never came back
+    Abandoned,
+    /// A player ran out of time
+    TimedOut,
+}
+
+impl GameResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameResult::RedWin => "red_win",
+            GameResult::BlackWin => "black_win",
+            GameResult::Draw => "draw",
+            GameResult::Resigned => "resigned",
+            GameResult::Abandoned => "abandoned",
+            GameResult::TimedOut => "timed_out",
+        }
+    }
+
+    /// How a game that was played to the end on the board finished.
+    pub fn from_winner(winner: Option<Colour>) -> GameResult {
+        match winner {
+            Some(Colour::Red) => GameResult::RedWin,
+            Some(Colour::Black) => GameResult::BlackWin,
+            None => GameResult::Draw,
+        }
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GameResult, String> {
+        match s {
+            "red_win" => Ok(GameResult::RedWin),
+            "black_win" => Ok(GameResult::BlackWin),
+            "draw" => Ok(GameResult::Draw),
+            "resigned" => Ok(GameResult::Resigned),
+            "abandoned" => Ok(GameResult::Abandoned),
+            "timed_out" => Ok(GameResult::TimedOut),
+            _ => Err(format!("unknown game result {s}")),
+        }
+    }
+}
+
+/// Reasons why a move can't be applied to a board.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MoveError {
+    RowOutOfRange(usize),
+    RowFull(usize),
+    ColumnOutOfRange(usize),
+    ColumnFull(usize),
+    IllegalDirection(Direction),
+    PopNotAllowed,
+    CannotPop(usize),
+    GameOver,
+    NotYourTurn,
+    NotAParticipant,
+    /// Games kept only for looking at, such as imported ones, which nobody plays on in
+    AnalysisOnly,
+    NothingToTakeBack,
+    NoTakebackToAnswer,
+    NoDrawToAnswer,
+    /// Draws offered to the computer, which never takes them
+    DrawDeclined,
+    UnknownGame(i32),
+    /// A move sent without a game id by someone playing more than one game
+    WhichGame,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::RowOutOfRange(row) => write!(f, "row {row} is out of range"),
+            MoveError::RowFull(row) => write!(f, "row {row} is full"),
+            MoveError::ColumnOutOfRange(column) => write!(f, "column {column} is out of range"),
+            MoveError::ColumnFull(column) => write!(f, "column {column} is full"),
+            MoveError::IllegalDirection(direction) => {
+                write!(f, "cannot play in direction {direction:?}")
+            }
+            MoveError::PopNotAllowed => write!(f, "pieces cannot be popped in this game"),
+            MoveError::CannotPop(row) => {
+                write!(
+                    f,
+                    "none of your pieces can be popped from there in row {row}"
+                )
+            }
+            MoveError::GameOver => write!(f, "the game is over"),
+            MoveError::NotYourTurn => write!(f, "it is not your turn"),
+            MoveError::NotAParticipant => write!(f, "you are not playing in this game"),
+            MoveError::AnalysisOnly => write!(f, "this game is only for looking at"),
+            MoveError::NothingToTakeBack => write!(f, "you have no move to take back"),
+            MoveError::NoTakebackToAnswer => {
+                write!(f, "the other player has not asked for a takeback")
+            }
+            MoveError::NoDrawToAnswer => write!(f, "the other player has not offered a draw"),
+            MoveError::DrawDeclined => write!(f, "the computer does not accept draws"),
+            MoveError::UnknownGame(game_id) => write!(f, "there is no game {game_id}"),
+            MoveError::WhichGame => {
+                write!(f, "you are playing more than one game, so say which one")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// A board of squares that only accepts moves allowed by its ruleset.
+#[derive(Debug, Clone)]
+pub struct Board {
+    squares: Squares,
+    win_length: usize,
+    ruleset: Ruleset,
+    /// How many moves have been made, which decides whose turn it is
+    ply: usize,
+}
+
+impl Board {
+    /// Makes a board, assuming one move was made for every piece on it, which holds
+    /// unless pieces have been popped.
+    pub fn new(squares: Squares, win_length: usize, ruleset: Ruleset) -> Board {
+        let ply = squares.iter().flatten().flatten().count();
+        Board {
+            squares,
+            win_length,
+            ruleset,
+            ply,
+        }
+    }
+
+    pub fn with_ply(self, ply: usize) -> Board {
+        Board { ply, ..self }
+    }
+
+    pub fn empty(settings: &GameSettings) -> Board {
+        Board::new(
+            vec![vec![None; settings.columns]; settings.rows],
+            settings.win_length,
+            settings.ruleset,
+        )
+    }
+
+    /// Plays the moves in turn on an empty board. Nothing is needed of the board along
+    /// the way, so they're played on a `Position`, which is much quicker.
+    pub fn from_moves(settings: &GameSettings, plays: &[Play]) -> Result<Board, MoveError> {
+        let mut position = Position::from_board(&Board::empty(settings));
+        for play in plays {
+            let colour = position.current_player().ok_or(MoveError::GameOver)?;
+            position.play(colour, play)?;
+        }
+        let board = Board::new(position.squares(), settings.win_length, settings.ruleset);
+        Ok(board.with_ply(position.ply()))
+    }
+
+    pub fn squares(&self) -> &Squares {
+        &self.squares
+    }
+
+    pub fn win_length(&self) -> usize {
+        self.win_length
+    }
+
+    pub fn ruleset(&self) -> Ruleset {
+        self.ruleset
+    }
+
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    /// Whose move it would be if the game weren't over.
+    fn side_to_move(&self) -> Colour {
+        match self.ply % 2 {
+            0 => Colour::Red,
+            _ => Colour::Black,
+        }
+    }
+
+    fn last_mover(&self) -> Option<Colour> {
+        match self.ply {
+            0 => None,
+            _ => Some(self.side_to_move().other()),
+        }
+    }
+
+    /// Every move the ruleset allows for whoever's turn it is.
+    pub fn legal_moves(&self) -> Vec<Play> {
+        self.ruleset.rules().legal_moves(self, self.side_to_move())
+    }
+
+    /// Whose turn it is, or `None` if the game is over.
+    pub fn current_player(&self) -> Option<Colour> {
+        if self.winner().is_some() || self.legal_moves().is_empty() {
+            None
+        } else {
+            Some(self.side_to_move())
+        }
+    }
+
+    pub fn winner(&self) -> Option<Colour> {
+        self.ruleset.rules().winner(self, self.last_mover())
+    }
+
+    pub fn win_lines(&self) -> Vec<WinLine> {
+        find_win_lines(&self.squares, self.win_length)
+    }
+
+    /// Makes a move for the given colour, checking that it's that colour's turn and
+    /// that the ruleset allows the move.
+    pub fn play(&mut self, colour: Colour, play: &Play) -> Result<MoveOutcome, MoveError> {
+        if self.current_player().ok_or(MoveError::GameOver)? != colour {
+            return Err(MoveError::NotYourTurn);
+        }
+        self.ruleset.rules().apply(self, colour, play)?;
+        self.ply += 1;
+
+        Ok(match (self.winner(), self.current_player()) {
+            (Some(winner), _) => MoveOutcome::Win(winner),
+            (None, Some(next)) => MoveOutcome::Continue(next),
+            (None, None) => MoveOutcome::Draw,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Game {
+    game_id: i32,
+    squares: Squares,
+    winner: Option<Colour>,
+    win_lines: Vec<WinLine>,
+    current_player: Option<Colour>,
+    win_length: usize,
+    ruleset: Ruleset,
+    your_colour: Colour,
+    your_name: String,
+    their_name: String,
+    /// Whoever has asked to take back their last move, until the other player answers
+    takeback_by: Option<Colour>,
+    /// Whoever has offered a draw, until the other player answers
+    draw_offered_by: Option<Colour>,
+    /// How the game ended, once it has
+    result: Option<GameResult>,
+}
+
+impl Game {
+    /// Where the game stands, as the player of the given colour sees it.
+    fn new(
+        game: &game::Model,
+        board: &Board,
+        your_colour: Colour,
+        your_name: &str,
+        their_name: &str,
+    ) -> Game {
+        let settings = game.settings();
+        // Games can end off the board, so what's stored has the last word
+        let (current_player, winner) = if game.finished {
+            let winner = game.winner_id.and_then(|winner| game.colour_of(winner));
+            (None, winner.or_else(|| board.winner()))
+        } else {
+            (board.current_player(), board.winner())
+        };
+        Game {
+            game_id: game.id,
+            squares: board.squares().to_vec(),
+            winner,
+            win_lines: board.win_lines(),
+            current_player,
+            win_length: settings.win_length,
+            ruleset: settings.ruleset,
+            your_colour,
+            your_name: your_name.to_owned(),
+            their_name: their_name.to_owned(),
+            takeback_by: game.takeback_by.and_then(|player| game.colour_of(player)),
+            draw_offered_by: game
+                .draw_offered_by
+                .and_then(|player| game.colour_of(player)),
+            result: game.result(),
+        }
+    }
+}
+
+/// What a player can ask of their game, besides where it stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Play(Play),
+    Takeback(Takeback),
+    Draw(Draw),
+    Resign,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Play(play) => write!(f, "{play}"),
+            Action::Takeback(takeback) => write!(f, "takeback {takeback:?}"),
+            Action::Draw(draw) => write!(f, "draw {draw:?}"),
+            Action::Resign => write!(f, "resignation"),
+        }
+    }
+}
+
+/// How many times a move is tried against fresh state when someone else saves the game
+/// first, before giving up on it.
+const SAVE_ATTEMPTS: usize = 3;
+
+/// Carries out what the client asked of the game with the given id, if anything, and
+/// sends everyone in the game where it stands. Without an id it's the client's only game,
+/// and with nothing asked they're sent every game they're playing and the lobby. If the
+/// store fails the client is told so, and can try again.
+pub async fn play_piece(
+    client: &Client,
+    clients: &Clients,
+    sockets: &Sockets,
+    game_id: Option<i32>,
+    action: Option<Action>,
+    db: &Db,
+) {
+    for _ in 0..SAVE_ATTEMPTS {
+        match try_play_piece(client, clients, sockets, game_id, action, db).await {
+            Ok(()) => return,
+            // Someone else moved first, so play it again against the game as it is now
+            Err(DbError::Conflict(game_id)) => {
+                println!(
+                    "Game {} changed under {}, retrying",
+                    game_id, client.username
+                )
+            }
+            Err(err) => {
+                eprintln!("error playing for {}: {}", client.username, err);
+                send_error_frame(client, game_id, SERVER_ERROR);
+                return;
+            }
+        }
+    }
+    send_error_frame(
+        client,
+        game_id,
+        "the game changed while your move was being played",
+    );
+}
+
+async fn try_play_piece(
+    client: &Client,
+    clients: &Clients,
+    sockets: &Sockets,
+    game_id: Option<i32>,
+    action: Option<Action>,
+    db: &Db,
+) -> Result<(), DbError> {
+    if client.username == "AI" {
+        let game = db.get_ai_game(&client.settings).await?;
+        return play_game(client, clients, sockets, game, action, db).await;
+    }
+    if let Some(game_id) = game_id {
+        return match db.find_game(game_id).await? {
+            Some(game) => play_game(client, clients, sockets, game, action, db).await,
+            None => {
+                let err = MoveError::UnknownGame(game_id);
+                match action {
+                    Some(action) => send_error(client, Some(game_id), &action, err),
+                    None => send_error_frame(client, Some(game_id), &err.to_string()),
+                }
+                Ok(())
+            }
+        };
+    }
+
+    let mut games = db.get_player_games(client.user_id).await?;
+    match action {
+        Some(action) if games.len() != 1 => {
+            let err = if games.is_empty() {
+                MoveError::NotAParticipant
+            } else {
+                MoveError::WhichGame
+            };
+            send_error(client, None, &action, err);
+            Ok(())
+        }
+        Some(action) => {
+            play_game(client, clients, sockets, games.remove(0), Some(action), db).await
+        }
+        None => {
+            for game in games {
+                play_game(client, clients, sockets, game, None, db).await?;
+            }
+            // Whatever they're playing, players can always look for another game
+            lobby::send_seeks(client, db).await
+        }
+    }
+}
+
+/// Carries out the action on the game, and sends both players where it stands.
+async fn play_game(
+    client: &Client,
+    clients: &Clients,
+    sockets: &Sockets,
+    mut game: game::Model,
+    action: Option<Action>,
+    db: &Db,
+) -> Result<(), DbError> {
+    let is_ai_game = client.username == "AI";
+    if let (true, Some(action)) = (game.analysis, action) {
+        send_error(client, Some(game.id), &action, MoveError::AnalysisOnly);
+        return Ok(());
+    }
+    let Some(your_colour) = game.colour_of(client.user_id) else {
+        // Anyone can look at a game, but only its players can do anything in it
+        return match action {
+            Some(action) => {
+                send_error(client, Some(game.id), &action, MoveError::NotAParticipant);
+                Ok(())
+            }
+            None => watch_game(client, &game, db).await,
+        };
+    };
+    let other_colour = your_colour.other();
+    let that_player_id = match your_colour {
+        Colour::Red => game.player_black_id,
+        Colour::Black => game.player_red_id,
+    };
+
+    // What was read, to tell whether anything needs saving
+    let original = game.clone();
+    let mut board = game.board()?;
+    // Moves made this time round, to go in the game's history once it's saved
+    let mut moves = Vec::new();
+    // The ply of the first move taken back, to forget it and those after once saved
+    let mut taken_back = None;
+    if let Some(Action::Takeback(takeback)) = action {
+        let history = db.get_moves(game.id).await?;
+        let outcome = if game.finished {
+            Err(MoveError::GameOver)
+        } else if history.len() != board.ply() {
+            // Games from before moves were recorded can't be rolled back
+            Err(MoveError::NothingToTakeBack)
+        } else {
+            answer_takeback(&mut game, client.user_id, takeback, is_ai_game, &history)
+        };
+        match outcome {
+            Err(err) => {
+                send_error(client, Some(game.id), &Action::Takeback(takeback), err);
+                return Ok(());
+            }
+            Ok(Some(ply)) => {
+                println!("{} took back moves from {}", client.username, ply);
+                let plays: Vec<Play> = history[..ply - 1]
+                    .iter()
+                    .map(|record| record.play())
+                    .collect();
+                board = Board::from_moves(&game.settings(), &plays)
+                    .map_err(|_| DbError::CorruptGame(game.id))?;
+                game.squares = serde_json::to_value(board.squares()).unwrap();
+                game.ply = board.ply() as i32;
+                taken_back = Some(ply);
+            }
+            Ok(None) => (),
+        }
+    }
+    if let Some(Action::Play(play)) = action {
+        let outcome = if game.finished {
+            Err(MoveError::GameOver)
+        } else {
+            board.play(your_colour, &play)
+        };
+        if outcome.is_ok() {
+            println!("{} played {}", client.username, play);
+            moves.push((board.ply(), client.user_id, play));
+            // Playing on answers any takeback asked for or draw offered
+            game.takeback_by = None;
+            game.draw_offered_by = None;
+        }
+        match outcome {
+            Err(err) => {
+                send_error(client, Some(game.id), &Action::Play(play), err);
+                return Ok(());
+            }
+            Ok(MoveOutcome::Continue(_)) if is_ai_game => {
+                // Searching can take a while, so keep it off the async runtime
+                let strategy = client.difficulty.strategy();
+                let position = board.clone();
+                let ai_play =
+                    tokio::task::spawn_blocking(move || strategy.choose_move(&position)).await;
+                match ai_play {
+                    Ok(Some(ai_play)) => {
+                        println!("AI played {}", ai_play);
+                        match board.play(other_colour, &ai_play) {
+                            Ok(_) => {
+                                if let Some(ai_id) = that_player_id {
+                                    moves.push((board.ply(), ai_id, ai_play));
+                                }
+                            }
+                            Err(err) => eprintln!("AI could not play {}: {}", ai_play, err),
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) => eprintln!("AI failed to choose a move: {}", err),
+                }
+            }
+            Ok(_) => (),
+        }
+        game.squares = serde_json::to_value(board.squares()).unwrap();
+        game.ply = board.ply() as i32;
+    }
+    if let Some(action @ (Action::Draw(_) | Action::Resign)) = action {
+        // Whether this ends the game
+        let outcome = if game.finished || board.current_player().is_none() {
+            Err(MoveError::GameOver)
+        } else {
+            match action {
+                Action::Draw(draw) => answer_draw(&mut game, client.user_id, draw, is_ai_game),
+                // Resigning needs nobody to agree to it
+                _ => Ok(true),
+            }
+        };
+        match outcome {
+            Err(err) => {
+                send_error(client, Some(game.id), &action, err);
+                return Ok(());
+            }
+            Ok(true) if action == Action::Resign => {
+                println!("{} resigned game {}", client.username, game.id);
+                game.finish(GameResult::Resigned, Some(other_colour));
+            }
+            Ok(true) => {
+                println!("{} agreed to a draw in game {}", client.username, game.id);
+                game.finish(GameResult::Draw, None);
+            }
+            Ok(false) => (),
+        }
+    }
+    // Anything a player asks of the game, that wasn't refused, keeps it from being swept
+    // away as abandoned
+    if action.is_some() {
+        game.active_at = Utc::now().into();
+    }
+
+    let your_name = db.get_player_by_id(client.user_id).await?.name;
+    let their_name = player_name(that_player_id, db).await?.unwrap_or_default();
+
+    if !game.finished && board.current_player().is_none() {
+        let winner = board.winner();
+        game.finish(GameResult::from_winner(winner), winner);
+    }
+    let this_frame = Game::new(&game, &board, your_colour, &your_name, &their_name);
+    let that_frame = Game::new(&game, &board, other_colour, &their_name, &your_name);
+    // Only saved when something changed, so that just looking at a game doesn't get in
+    // the way of whoever is playing in it
+    if game != original {
+        db.save_game_and_moves(game, taken_back, &moves).await?;
+    }
+
+    let this_payload = serde_json::to_string(&this_frame).unwrap();
+    let that_payload = serde_json::to_string(&that_frame).unwrap();
+    notify_players(Some(client.user_id), this_payload, clients, sockets).await;
+    notify_players(that_player_id, that_payload, clients, sockets).await;
+    Ok(())
+}
+
+/// Sends both players of a game where it stands, for when it changes without either of
+/// them doing anything.
+pub async fn send_game(
+    game: &game::Model,
+    clients: &Clients,
+    sockets: &Sockets,
+    db: &Db,
+) -> Result<(), DbError> {
+    let board = game.board()?;
+    let red_name = player_name(game.player_red_id, db)
+        .await?
+        .unwrap_or_default();
+    let black_name = player_name(game.player_black_id, db)
+        .await?
+        .unwrap_or_default();
+    let red_frame = Game::new(game, &board, Colour::Red, &red_name, &black_name);
+    let black_frame = Game::new(game, &board, Colour::Black, &black_name, &red_name);
+    let red_payload = serde_json::to_string(&red_frame).unwrap();
+    let black_payload = serde_json::to_string(&black_frame).unwrap();
+    notify_players(game.player_red_id, red_payload, clients, sockets).await;
+    notify_players(game.player_black_id, black_payload, clients, sockets).await;
+    Ok(())
+}
+
+/// Sends someone who isn't playing in a game where it stands, as red sees it, without
+/// telling either player.
+async fn watch_game(client: &Client, game: &game::Model, db: &Db) -> Result<(), DbError> {
+    let board = game.board()?;
+    let red_name = player_name(game.player_red_id, db)
+        .await?
+        .unwrap_or_default();
+    let black_name = player_name(game.player_black_id, db)
+        .await?
+        .unwrap_or_default();
+    let frame = Game::new(game, &board, Colour::Red, &red_name, &black_name);
+    if let Some(sender) = &client.sender {
+        let payload = serde_json::to_string(&frame).unwrap();
+        if let Err(err) = sender.send(Ok(Message::text(payload))) {
+            eprintln!("error sending game to {}: {}", client.username, err);
+        }
+    }
+    Ok(())
+}
+
+/// The player's name, or `None` for the other side of a game still waiting for them.
+pub(crate) async fn player_name(
+    player_id: Option<i32>,
+    db: &Db,
+) -> Result<Option<String>, DbError> {
+    match player_id {
+        Some(player_id) => Ok(Some(db.get_player_by_id(player_id).await?.name)),
+        None => Ok(None),
+    }
+}
+
+fn place_piece(current_player: Option<Colour>, play: &Play, squares: &mut Squares) {
+    if let Some(colour) = current_player {
+        let square = match play.direction {
+            Direction::Right => squares[play.line]
+                .iter_mut()
+                .find(|square| square.is_none()),
+            Direction::Left => squares[play.line]
+                .iter_mut()
+                .rfind(|square| square.is_none()),
+            Direction::Down => squares
+                .iter_mut()
+                .map(|row| &mut row[play.line])
+                .find(|square| square.is_none()),
+            Direction::Up => squares
+                .iter_mut()
+                .map(|row| &mut row[play.line])
+                .rfind(|square| square.is_none()),
+        };
+        if let Some(square) = square {
+            *square = Some(Square {
+                value: colour,
+                direction: play.direction,
+            })
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorFrame {
+    /// The game the request was about, if it was about one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_id: Option<i32>,
+    error: String,
+}
+
+/// Tells only the websocket that made a bad request what went wrong.
+fn send_error(client: &Client, game_id: Option<i32>, action: &Action, err: MoveError) {
+    println!("Rejecting {} from {}: {}", action, client.username, err);
+    send_error_frame(client, game_id, &err.to_string());
+}
+
+/// What a client is told when the store fails, since it's nothing they did wrong.
+pub(crate) const SERVER_ERROR: &str = "something went wrong on the server, please try again";
+
+pub(crate) fn send_error_frame(client: &Client, game_id: Option<i32>, error: &str) {
+    if let Some(sender) = &client.sender {
+        let payload = serde_json::to_string(&ErrorFrame {
+            game_id,
+            error: error.to_owned(),
+        })
+        .unwrap();
+        if let Err(err) = sender.send(Ok(Message::text(payload))) {
+            eprintln!("error sending error frame: {}", err);
+        }
+    }
+}
+
+async fn notify_players(
+    player_id: Option<i32>,
+    payload: String,
+    clients: &Clients,
+    sockets: &Sockets,
+) {
+    if let Some(player_id) = player_id {
+        // Only held while sending, so nobody waits on a move being played
+        let clients = clients.read().await;
+        if let Some(sockets) = sockets.read().await.get(&player_id) {
+            for uuid in sockets {
+                if let Some(client) = clients.get(uuid) {
+                    if let Some(sender) = &client.sender {
+                        println!("Notifying {} of play at {}", client.username, uuid);
+                        if let Err(err) = sender.send(Ok(Message::text(&payload))) {
+                            eprintln!("error notifying {}: {}", client.username, err);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn calculate_winner(squares: &Squares, win_length: usize) -> Option<Colour> {
+    find_win_lines(squares, win_length)
+        .first()
+        .map(|line| line.colour)
+}
+
+/// Finds every winning line on the board. A run longer than the win length counts as
+/// a single line.
+fn find_win_lines(squares: &Squares, win_length: usize) -> Vec<WinLine> {
+    let rows = squares.len() as isize;
+    let columns = squares.first().map_or(0, |row| row.len()) as isize;
+    let colour_at = |i: isize, j: isize| {
+        if i < 0 || i >= rows || j < 0 || j >= columns {
+            None
+        } else {
+            squares[i as usize][j as usize].map(|square| square.value)
+        }
+    };
+
+    let mut lines = Vec::new();
+    // horizontal, vertical, diagonal and anti-diagonal
+    for (dx, dy) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+        for i in 0..rows {
+            for j in 0..columns {
+                let colour = match colour_at(i, j) {
+                    Some(colour) => colour,
+                    None => continue,
+                };
+                // Only follow runs from their first square
+                if colour_at(i - dx, j - dy) == Some(colour) {
+                    continue;
+                }
+
+                let mut line = Vec::new();
+                let (mut x, mut y) = (i, j);
+                while colour_at(x, y) == Some(colour) {
+                    line.push((x as usize, y as usize));
+                    x += dx;
+                    y += dy;
+                }
+                if line.len() >= win_length {
+                    lines.push(WinLine {
+                        colour,
+                        squares: line,
+                    });
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{start_game, GameStore, MemoryStore};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    const B: Option<Square> = Some(Square {
+        value: Colour::Black,
+        direction: Direction::Left,
+    });
+    const R: Option<Square> = Some(Square {
+        value: Colour::Red,
+        direction: Direction::Left,
+    });
+
+    #[test]
+    fn test_calculate_current_player() {
+        let u: Option<Square> = None;
+
+        let empty: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+        ];
+        assert_eq!(
+            Board::new(empty, 4, Ruleset::Stacked).current_player(),
+            Some(Colour::Red)
+        );
+
+        let draw: Squares = vec![
+            vec![R, B, R, B, R, B, R],
+            vec![B, R, B, R, B, R, B],
+            vec![R, R, B, B, R, R, R],
+            vec![B, B, R, B, R, B, B],
+            vec![B, R, R, B, B, R, R],
+            vec![B, B, B, R, B, R, R],
+            vec![R, B, B, B, R, R, R],
+        ];
+        assert_eq!(Board::new(draw, 4, Ruleset::Stacked).current_player(), None);
+    }
+
+    #[test]
+    fn test_calculate_win() {
+        let u: Option<Square> = None;
+
+        let empty: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+        ];
+        assert_eq!(calculate_winner(&empty, 4), None);
+
+        let no_win: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, R, u, u, u, u, u],
+            vec![u, R, R, R, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, B],
+            vec![u, u, u, u, B, B, B],
+        ];
+        assert_eq!(calculate_winner(&no_win, 4), None);
+
+        let vert: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, R, R, R],
+        ];
+        assert_eq!(calculate_winner(&vert, 4), Some(Colour::Black));
+        assert_eq!(
+            find_win_lines(&vert, 4),
+            vec![WinLine {
+                colour: Colour::Black,
+                squares: vec![(1, 2), (2, 2), (3, 2), (4, 2)],
+            }]
+        );
+        assert_eq!(vert[4][2].unwrap().direction, Direction::Left);
+        assert_eq!(vert[6][6].unwrap().direction, Direction::Left);
+
+        let horz: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, R, R, R, R],
+        ];
+        assert_eq!(calculate_winner(&horz, 4), Some(Colour::Red));
+        assert_eq!(
+            find_win_lines(&horz, 4),
+            vec![WinLine {
+                colour: Colour::Red,
+                squares: vec![(6, 3), (6, 4), (6, 5), (6, 6)],
+            }]
+        );
+        assert_eq!(horz[6][6].unwrap().direction, Direction::Left);
+        assert_eq!(horz[1][2].unwrap().direction, Direction::Left);
+
+        let diag1: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, u, B, R, u, u, u],
+            vec![u, u, u, u, R, u, u],
+            vec![u, u, u, u, u, R, u],
+            vec![u, u, u, u, u, u, R],
+        ];
+        assert_eq!(calculate_winner(&diag1, 4), Some(Colour::Red));
+        assert_eq!(
+            find_win_lines(&diag1, 4),
+            vec![WinLine {
+                colour: Colour::Red,
+                squares: vec![(3, 3), (4, 4), (5, 5), (6, 6)],
+            }]
+        );
+        assert_eq!(diag1[3][3].unwrap().direction, Direction::Left);
+        assert_eq!(diag1[2][2].unwrap().direction, Direction::Left);
+
+        let diag2: Squares = vec![
+            vec![u, u, u, u, u, u, B],
+            vec![u, u, u, u, u, B, u],
+            vec![u, u, u, u, B, u, u],
+            vec![u, u, u, B, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, R, R, R],
+        ];
+        assert_eq!(calculate_winner(&diag2, 4), Some(Colour::Black));
+        assert_eq!(
+            find_win_lines(&diag2, 4),
+            vec![WinLine {
+                colour: Colour::Black,
+                squares: vec![(0, 6), (1, 5), (2, 4), (3, 3)],
+            }]
+        );
+        assert_eq!(diag2[2][4].unwrap().direction, Direction::Left);
+        assert_eq!(diag2[6][5].unwrap().direction, Direction::Left);
+
+        let diag3: Squares = vec![
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, B, u, u, u],
+            vec![u, u, B, u, u, u, u],
+            vec![u, B, u, u, u, u, u],
+            vec![B, u, u, u, R, R, R],
+        ];
+        assert_eq!(calculate_winner(&diag3, 4), Some(Colour::Black));
+        assert_eq!(
+            find_win_lines(&diag3, 4),
+            vec![WinLine {
+                colour: Colour::Black,
+                squares: vec![(3, 3), (4, 2), (5, 1), (6, 0)],
+            }]
+        );
+        assert_eq!(diag3[6][0].unwrap().direction, Direction::Left);
+        assert_eq!(diag3[6][5].unwrap().direction, Direction::Left);
+    }
+
+    #[test]
+    fn test_board_play() {
+        let u: Option<Square> = None;
+
+        let mut board = Board::empty(&GameSettings::default());
+        assert_eq!(
+            board.play(
+                Colour::Red,
+                &Play {
+                    line: 3,
+                    direction: Direction::Right,
+                    pop: false,
+                }
+            ),
+            Ok(MoveOutcome::Continue(Colour::Black))
+        );
+        assert_eq!(board.squares()[3][0].unwrap().value, Colour::Red);
+        assert_eq!(
+            board.play(
+                Colour::Red,
+                &Play {
+                    line: 3,
+                    direction: Direction::Left,
+                    pop: false,
+                }
+            ),
+            Err(MoveError::NotYourTurn)
+        );
+        assert_eq!(
+            board.play(
+                Colour::Black,
+                &Play {
+                    line: 3,
+                    direction: Direction::Left,
+                    pop: false,
+                }
+            ),
+            Ok(MoveOutcome::Continue(Colour::Red))
+        );
+        assert_eq!(board.squares()[3][6].unwrap().value, Colour::Black);
+
+        assert_eq!(
+            board.play(
+                Colour::Red,
+                &Play {
+                    line: 7,
+                    direction: Direction::Left,
+                    pop: false,
+                }
+            ),
+            Err(MoveError::RowOutOfRange(7))
+        );
+
+        let mut full_row = Board::new(
+            vec![
+                vec![R, B, R, B, R, B, R],
+                vec![B, R, B, R, B, R, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+            ],
+            4,
+            Ruleset::Stacked,
+        );
+        assert_eq!(
+            full_row.play(
+                Colour::Black,
+                &Play {
+                    line: 0,
+                    direction: Direction::Right,
+                    pop: false,
+                }
+            ),
+            Err(MoveError::RowFull(0))
+        );
+
+        let mut won = Board::new(
+            vec![
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![B, B, B, u, u, u, u],
+                vec![u, u, u, u, u, u, u],
+                vec![u, u, u, R, R, R, u],
+            ],
+            4,
+            Ruleset::Stacked,
+        );
+        assert_eq!(
+            won.play(
+                Colour::Red,
+                &Play {
+                    line: 6,
+                    direction: Direction::Left,
+                    pop: false,
+                }
+            ),
+            Ok(MoveOutcome::Win(Colour::Red))
+        );
+        assert_eq!(
+            won.play(
+                Colour::Black,
+                &Play {
+                    line: 4,
+                    direction: Direction::Right,
+                    pop: false,
+                }
+            ),
+            Err(MoveError::GameOver)
+        );
+    }
+
+    #[test]
+    fn test_rectangular_board() {
+        let u: Option<Square> = None;
+
+        let wide: Squares = vec![
+            vec![u, u, u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u, u, u],
+            vec![u, u, u, B, B, B, B, u, u],
+            vec![u, u, u, u, R, R, R, R, R],
+        ];
+        assert_eq!(calculate_winner(&wide, 5), Some(Colour::Red));
+
+        let tall: Squares = vec![
+            vec![u, u, u, B],
+            vec![u, u, B, u],
+            vec![u, B, u, u],
+            vec![R, R, u, R],
+            vec![u, u, u, u],
+            vec![u, u, u, u],
+        ];
+        assert_eq!(calculate_winner(&tall, 3), Some(Colour::Black));
+        assert_eq!(calculate_winner(&tall, 4), None);
+
+        let settings = GameSettings {
+            rows: 4,
+            columns: 9,
+            win_length: 5,
+            ..GameSettings::default()
+        };
+        assert!(settings.is_valid());
+        let mut board = Board::empty(&settings);
+        assert_eq!(board.squares().len(), 4);
+        assert_eq!(board.squares()[0].len(), 9);
+        assert_eq!(
+            board.play(
+                Colour::Red,
+                &Play {
+                    line: 3,
+                    direction: Direction::Left,
+                    pop: false,
+                }
+            ),
+            Ok(MoveOutcome::Continue(Colour::Black))
+        );
+        assert_eq!(board.squares()[3][8].unwrap().value, Colour::Red);
+
+        assert!(GameSettings::default().is_valid());
+        assert!(!GameSettings {
+            rows: 3,
+            columns: 7,
+            win_length: 3,
+            ..GameSettings::default()
+        }
+        .is_valid());
+        assert!(!GameSettings {
+            rows: 7,
+            columns: MAX_BOARD_SIZE + 1,
+            win_length: 4,
+            ..GameSettings::default()
+        }
+        .is_valid());
+        assert!(!GameSettings {
+            rows: 5,
+            columns: 6,
+            win_length: 7,
+            ..GameSettings::default()
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_all_win_lines() {
+        let u: Option<Square> = None;
+
+        // The last red piece at row 3, column 3 completes two lines at once, and the
+        // black row of five is a single line
+        let squares: Squares = vec![
+            vec![u, u, u, R, u, u, u],
+            vec![u, u, u, R, u, u, u],
+            vec![u, u, u, R, u, u, u],
+            vec![R, R, R, R, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, B, B, B, B, B, u],
+            vec![u, u, B, B, u, u, u],
+        ];
+        let board = Board::new(squares.clone(), 4, Ruleset::Stacked);
+        assert_eq!(
+            board.win_lines(),
+            vec![
+                WinLine {
+                    colour: Colour::Red,
+                    squares: vec![(3, 0), (3, 1), (3, 2), (3, 3)],
+                },
+                WinLine {
+                    colour: Colour::Black,
+                    squares: vec![(5, 1), (5, 2), (5, 3), (5, 4), (5, 5)],
+                },
+                WinLine {
+                    colour: Colour::Red,
+                    squares: vec![(0, 3), (1, 3), (2, 3), (3, 3)],
+                },
+            ]
+        );
+        assert_eq!(board.winner(), Some(Colour::Red));
+        assert_eq!(
+            serde_json::to_value(board.squares()).unwrap(),
+            serde_json::to_value(&squares).unwrap()
+        );
+    }
+
+    fn client(username: &str, user_id: i32) -> Client {
+        Client {
+            username: username.to_owned(),
+            user_id,
+            settings: GameSettings::default(),
+            difficulty: ai::Difficulty::default(),
+            sender: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_only_saves_games_that_change() {
+        let store = MemoryStore::new();
+        let game = start_game(&store, "alice", "bob").await;
+        let db: Db = Arc::new(store);
+        let (clients, sockets) = (Clients::default(), Sockets::default());
+        let alice = client("alice", game.player_red_id.unwrap());
+
+        play_piece(&alice, &clients, &sockets, Some(game.id), None, &db).await;
+        assert_eq!(db.find_game(game.id).await.unwrap().unwrap(), game);
+
+        let play = Action::Play("3R".parse().unwrap());
+        play_piece(&alice, &clients, &sockets, Some(game.id), Some(play), &db).await;
+        let played = db.find_game(game.id).await.unwrap().unwrap();
+        assert_eq!(played.version, game.version + 1);
+        assert_eq!(db.get_moves(game.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_players_act_in_games() {
+        let store = MemoryStore::new();
+        let game = start_game(&store, "alice", "bob").await;
+        let carol = store.get_player("carol").await.unwrap().id;
+        let db: Db = Arc::new(store);
+        let (clients, sockets) = (Clients::default(), Sockets::default());
+        // Alice is connected, to hear of anything done in her game
+        let (sender, mut to_alice) = mpsc::unbounded_channel();
+        let alice = game.player_red_id.unwrap();
+        clients.write().await.insert(
+            String::from("alice"),
+            Client {
+                sender: Some(sender),
+                ..client("alice", alice)
+            },
+        );
+        sockets
+            .write()
+            .await
+            .insert(alice, HashSet::from([String::from("alice")]));
+        let (sender, mut to_carol) = mpsc::unbounded_channel();
+        let carol = Client {
+            sender: Some(sender),
+            ..client("carol", carol)
+        };
+        let mut received = || {
+            let message = to_carol.try_recv().unwrap().unwrap();
+            message.to_str().unwrap().to_owned()
+        };
+
+        let play = Action::Play("3R".parse().unwrap());
+        play_piece(&carol, &clients, &sockets, Some(game.id), Some(play), &db).await;
+        assert!(received().contains(&MoveError::NotAParticipant.to_string()));
+        play_piece(
+            &carol,
+            &clients,
+            &sockets,
+            Some(game.id),
+            Some(Action::Resign),
+            &db,
+        )
+        .await;
+        assert!(received().contains(&MoveError::NotAParticipant.to_string()));
+
+        // Looking is allowed, and sees the game as red does
+        play_piece(&carol, &clients, &sockets, Some(game.id), None, &db).await;
+        let frame: Game = serde_json::from_str(&received()).unwrap();
+        assert_eq!(
+            (frame.your_colour, frame.your_name),
+            (Colour::Red, String::from("alice"))
+        );
+
+        assert!(to_alice.try_recv().is_err());
+        assert_eq!(db.find_game(game.id).await.unwrap().unwrap(), game);
+        assert!(db.get_moves(game.id).await.unwrap().is_empty());
+
+        // Not even its players can do anything in a game that's only for looking at
+        let analysis = db
+            .create_analysis_game(
+                alice,
+                game.player_black_id.unwrap(),
+                &game.settings(),
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        let alice = clients.read().await["alice"].clone();
+        for action in [play, Action::Resign] {
+            play_piece(
+                &alice,
+                &clients,
+                &sockets,
+                Some(analysis.id),
+                Some(action),
+                &db,
+            )
+            .await;
+            let message = to_alice.try_recv().unwrap().unwrap();
+            assert!(message
+                .to_str()
+                .unwrap()
+                .contains(&MoveError::AnalysisOnly.to_string()));
+        }
+        assert_eq!(db.find_game(analysis.id).await.unwrap().unwrap(), analysis);
+    }
+
+    #[test]
+    fn test_reads_old_win_squares() {
+        let squares: Squares = serde_json::from_str(
+            r#"[[{"value":"red","direction":"win"},{"value":"black","direction":"left"},null]]"#,
+        )
+        .unwrap();
+        assert_eq!(squares[0][0].unwrap().direction, Direction::Right);
+        assert_eq!(squares[0][1].unwrap().direction, Direction::Left);
+        assert_eq!(squares[0][2], None);
+    }
+}
@@ -0,0 +1,175 @@
+//! A short text form for moves: the row (or column) number followed by the direction's
+//! initial, with an `x` on the end for pops, so `3L`, `0R`, `5D` and `2Rx`. A sequence
+//! of moves is written with spaces between them.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::Direction;
+use crate::ws::Play;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseMoveError {
+    MissingRow(String),
+    MissingDirection(String),
+    UnknownDirection(String),
+}
+
+impl fmt::Display for ParseMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseMoveError::MissingRow(text) => write!(f, "move {text:?} has no row number"),
+            ParseMoveError::MissingDirection(text) => {
+                write!(f, "move {text:?} has no direction")
+            }
+            ParseMoveError::UnknownDirection(text) => {
+                write!(f, "move {text:?} has an unknown direction")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseMoveError {}
+
+impl Direction {
+    fn initial(self) -> char {
+        match self {
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+        }
+    }
+}
+
+impl fmt::Display for Play {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.line, self.direction.initial())?;
+        if self.pop {
+            write!(f, "x")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Play {
+    type Err = ParseMoveError;
+
+    fn from_str(s: &str) -> Result<Play, ParseMoveError> {
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let line = s[..digits]
+            .parse()
+            .map_err(|_| ParseMoveError::MissingRow(s.to_owned()))?;
+        let (direction, pop) = match &s[digits..] {
+            "" => return Err(ParseMoveError::MissingDirection(s.to_owned())),
+            rest => match rest.strip_suffix(['x', 'X']) {
+                Some(direction) => (direction, true),
+                None => (rest, false),
+            },
+        };
+        let direction = match direction {
+            "L" | "l" => Direction::Left,
+            "R" | "r" => Direction::Right,
+            "U" | "u" => Direction::Up,
+            "D" | "d" => Direction::Down,
+            _ => return Err(ParseMoveError::UnknownDirection(s.to_owned())),
+        };
+        Ok(Play {
+            line,
+            direction,
+            pop,
+        })
+    }
+}
+
+/// A whole game's worth of moves, in the order they were played.
+#[derive(Debug, Clone, Default)]
+pub struct Moves(pub Vec<Play>);
+
+impl fmt::Display for Moves {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, play) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{play}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Moves {
+    type Err = ParseMoveError;
+
+    /// Takes moves separated by whitespace or commas.
+    fn from_str(s: &str) -> Result<Moves, ParseMoveError> {
+        s.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|text| !text.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rules::Ruleset;
+    use crate::game::{place_piece, Board, Colour, GameSettings, Squares};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_parse_moves() {
+        let moves: Moves = "3L 5r,0Ux\n 10D".parse().unwrap();
+        assert_eq!(moves.to_string(), "3L 5R 0Ux 10D");
+        assert_eq!(moves.0[2].line, 0);
+        assert_eq!(moves.0[2].direction, Direction::Up);
+        assert!(moves.0[2].pop);
+
+        assert_eq!("".parse::<Moves>().unwrap().0.len(), 0);
+        assert_eq!(
+            "L".parse::<Play>(),
+            Err(ParseMoveError::MissingRow("L".to_owned()))
+        );
+        assert_eq!(
+            "3".parse::<Play>(),
+            Err(ParseMoveError::MissingDirection("3".to_owned()))
+        );
+        assert_eq!(
+            "3Q".parse::<Play>(),
+            Err(ParseMoveError::UnknownDirection("3Q".to_owned()))
+        );
+        assert!("3L 4".parse::<Moves>().is_err());
+    }
+
+    #[test]
+    fn test_round_trip_against_place_piece() {
+        let mut rng = StdRng::seed_from_u64(10);
+        for game in 0..50 {
+            let settings = GameSettings {
+                rows: rng.gen_range(4..=7),
+                columns: rng.gen_range(4..=7),
+                win_length: 4,
+                ruleset: [Ruleset::Stacked, Ruleset::FourSided][game % 2],
+            };
+            let mut board = Board::empty(&settings);
+            let mut played = Vec::new();
+            while let Some(colour) = board.current_player() {
+                let play = *board.legal_moves().choose(&mut rng).unwrap();
+                board.play(colour, &play).unwrap();
+                played.push(play);
+            }
+
+            let moves: Moves = Moves(played).to_string().parse().unwrap();
+            let mut squares: Squares = Board::empty(&settings).squares().clone();
+            let mut colour = Colour::Red;
+            for play in &moves.0 {
+                place_piece(Some(colour), play, &mut squares);
+                colour = colour.other();
+            }
+            assert_eq!(&squares, board.squares());
+        }
+    }
+}
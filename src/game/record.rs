@@ -0,0 +1,362 @@
+//! A whole game written down: who played it, on what board, how it went and how it
+//! ended. It's sent as JSON, or as text with a tag per line followed by the moves:
+//!
+//! ```text
+//! [Red "alice"]
+//! [Black "bob"]
+//! [Rows "7"]
+//! [Columns "7"]
+//! [WinLength "4"]
+//! [Ruleset "stacked"]
+//! [Result "red_win"]
+//! [Winner "red"]
+//!
+//! 3R 3L 2R 2L 1R 1L 0R
+//! ```
+//!
+//! Only the players are required in text; other tags fall back to their defaults and
+//! unknown tags are ignored.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::notation::{Moves, ParseMoveError};
+use super::{Board, Colour, GameResult, GameSettings, MoveError};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    pub red: Option<String>,
+    pub black: Option<String>,
+    #[serde(flatten)]
+    pub settings: GameSettings,
+    #[serde(default)]
+    pub result: Option<GameResult>,
+    #[serde(default)]
+    pub winner: Option<Colour>,
+    /// Every move in notation, separated by spaces
+    pub moves: String,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    #[serde(default)]
+    pub finished_at: Option<String>,
+}
+
+/// How a game ended, and who won it if anyone did.
+pub type Ending = (GameResult, Option<Colour>);
+
+/// When a game started and when it finished, where they're known.
+pub type Times = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// A record's moves, played through and checked by `GameRecord::replay`.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub board: Board,
+    pub moves: Moves,
+    /// How the game ended, if it has
+    pub ending: Option<Ending>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RecordError {
+    BadTag(String),
+    BadValue(&'static str, String),
+    MissingPlayer(Colour),
+    InvalidSettings,
+    Move(ParseMoveError),
+    IllegalMove(usize, MoveError),
+    /// The result doesn't fit how the moves left the board
+    WrongResult,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::BadTag(line) => write!(f, "can't read the tag {line:?}"),
+            RecordError::BadValue(tag, value) => write!(f, "{value:?} is not a valid {tag}"),
+            RecordError::MissingPlayer(colour) => write!(f, "the {colour:?} player is missing"),
+            RecordError::InvalidSettings => write!(f, "the board settings are not valid"),
+            RecordError::Move(err) => write!(f, "{err}"),
+            RecordError::IllegalMove(ply, err) => write!(f, "move {ply} is illegal: {err}"),
+            RecordError::WrongResult => {
+                write!(f, "the result does not match the position the moves reach")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl GameRecord {
+    /// Plays the moves through on the board they describe, and checks that the result
+    /// agrees with where they leave it.
+    pub fn replay(&self) -> Result<Replay, RecordError> {
+        if !self.settings.is_valid() {
+            return Err(RecordError::InvalidSettings);
+        }
+        let moves: Moves = self.moves.parse().map_err(RecordError::Move)?;
+        let mut board = Board::empty(&self.settings);
+        for (i, play) in moves.0.iter().enumerate() {
+            let colour = board
+                .current_player()
+                .ok_or(RecordError::IllegalMove(i + 1, MoveError::GameOver))?;
+            board
+                .play(colour, play)
+                .map_err(|err| RecordError::IllegalMove(i + 1, err))?;
+        }
+
+        let ending = match (board.current_player(), self.result) {
+            // Played out on the board, so the board decides
+            (None, result) => {
+                let winner = board.winner();
+                let played = GameResult::from_winner(winner);
+                if result.is_some_and(|result| result != played)
+                    || self.winner.is_some_and(|colour| Some(colour) != winner)
+                {
+                    return Err(RecordError::WrongResult);
+                }
+                Some((played, winner))
+            }
+            (Some(_), None) => None,
+            (Some(_), Some(GameResult::RedWin | GameResult::BlackWin)) => {
+                return Err(RecordError::WrongResult)
+            }
+            // Nobody wins a draw, and somebody wins every other way of ending off the board
+            (Some(_), Some(result)) => match (result, self.winner) {
+                (GameResult::Draw, None) => Some((result, None)),
+                (GameResult::Draw, Some(_)) | (_, None) => return Err(RecordError::WrongResult),
+                (_, Some(winner)) => Some((result, Some(winner))),
+            },
+        };
+        Ok(Replay {
+            board,
+            moves,
+            ending,
+        })
+    }
+
+    /// When the game started and when it finished, where the record says.
+    pub fn times(&self) -> Result<Times, RecordError> {
+        Ok((
+            parse_time(self.created_at.as_deref())?,
+            parse_time(self.finished_at.as_deref())?,
+        ))
+    }
+
+    /// Both players' names, which every stored game has.
+    pub fn players(&self) -> Result<(&str, &str), RecordError> {
+        match (&self.red, &self.black) {
+            (Some(red), Some(black)) => Ok((red, black)),
+            (None, _) => Err(RecordError::MissingPlayer(Colour::Red)),
+            (_, None) => Err(RecordError::MissingPlayer(Colour::Black)),
+        }
+    }
+}
+
+fn write_tag(f: &mut fmt::Formatter, tag: &str, value: Option<&str>) -> fmt::Result {
+    match value {
+        Some(value) => writeln!(f, "[{tag} \"{value}\"]"),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tag(f, "Red", self.red.as_deref())?;
+        write_tag(f, "Black", self.black.as_deref())?;
+        write_tag(f, "Rows", Some(&self.settings.rows.to_string()))?;
+        write_tag(f, "Columns", Some(&self.settings.columns.to_string()))?;
+        write_tag(f, "WinLength", Some(&self.settings.win_length.to_string()))?;
+        write_tag(f, "Ruleset", Some(self.settings.ruleset.as_str()))?;
+        write_tag(f, "Result", self.result.map(GameResult::as_str))?;
+        write_tag(f, "Winner", self.winner.map(Colour::as_str))?;
+        write_tag(f, "Created", self.created_at.as_deref())?;
+        write_tag(f, "Updated", self.updated_at.as_deref())?;
+        write_tag(f, "Finished", self.finished_at.as_deref())?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.moves)
+    }
+}
+
+fn parse_time(value: Option<&str>) -> Result<Option<DateTime<Utc>>, RecordError> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|_| RecordError::BadValue("time", value.to_owned()))
+        })
+        .transpose()
+}
+
+fn parse_value<T: FromStr>(tag: &'static str, value: &str) -> Result<T, RecordError> {
+    value
+        .parse()
+        .map_err(|_| RecordError::BadValue(tag, value.to_owned()))
+}
+
+impl FromStr for GameRecord {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<GameRecord, RecordError> {
+        let mut record = GameRecord {
+            red: None,
+            black: None,
+            settings: GameSettings::default(),
+            result: None,
+            winner: None,
+            moves: String::new(),
+            created_at: None,
+            updated_at: None,
+            finished_at: None,
+        };
+        let mut moves = Vec::new();
+        for line in s.lines().map(str::trim) {
+            if !line.starts_with('[') {
+                moves.push(line);
+                continue;
+            }
+            let (tag, value) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(tag, value)| {
+                    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+                    Some((tag, value))
+                })
+                .ok_or_else(|| RecordError::BadTag(line.to_owned()))?;
+            let text = Some(value.to_owned());
+            match tag {
+                "Red" => record.red = text,
+                "Black" => record.black = text,
+                "Rows" => record.settings.rows = parse_value("row count", value)?,
+                "Columns" => record.settings.columns = parse_value("column count", value)?,
+                "WinLength" => record.settings.win_length = parse_value("win length", value)?,
+                "Ruleset" => record.settings.ruleset = parse_value("ruleset", value)?,
+                "Result" => record.result = Some(parse_value("result", value)?),
+                "Winner" => record.winner = Some(parse_value("colour", value)?),
+                "Created" => record.created_at = text,
+                "Updated" => record.updated_at = text,
+                "Finished" => record.finished_at = text,
+                _ => (),
+            }
+        }
+        record.moves = moves.join(" ").trim().to_owned();
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::rules::Ruleset;
+    use crate::game::Direction;
+
+    fn record(moves: &str) -> GameRecord {
+        GameRecord {
+            red: Some(String::from("alice")),
+            black: Some(String::from("bob")),
+            settings: GameSettings::default(),
+            result: None,
+            winner: None,
+            moves: moves.to_owned(),
+            created_at: Some(String::from("2024-01-01T00:00:00+00:00")),
+            updated_at: None,
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut game = record("0R 6R 0R 6R 0R 5L 0R");
+        game.settings.ruleset = Ruleset::PopOut;
+        game.result = Some(GameResult::RedWin);
+        game.winner = Some(Colour::Red);
+        let text = game.to_string();
+        assert!(text.starts_with("[Red \"alice\"]\n[Black \"bob\"]\n"));
+        assert_eq!(text.parse::<GameRecord>(), Ok(game));
+
+        let short: GameRecord = "[Red \"a\"]\n[Black \"b\"]\n[Rows \"5\"]\n3L 3R\n2L"
+            .parse()
+            .unwrap();
+        assert_eq!(short.settings.rows, 5);
+        assert_eq!(short.moves, "3L 3R 2L");
+        assert_eq!(
+            "[Rows 5]".parse::<GameRecord>(),
+            Err(RecordError::BadTag(String::from("[Rows 5]")))
+        );
+        assert_eq!(
+            "[Rows \"lots\"]".parse::<GameRecord>(),
+            Err(RecordError::BadValue("row count", String::from("lots")))
+        );
+    }
+
+    #[test]
+    fn test_replay_checks_moves_and_result() {
+        let replay = record("0R 6R 0R 6R 0R 5L 0R").replay().unwrap();
+        assert_eq!(replay.board.ply(), 7);
+        assert_eq!(replay.moves.0.len(), 7);
+        assert_eq!(replay.ending, Some((GameResult::RedWin, Some(Colour::Red))));
+
+        assert_eq!(record("3R 3L").replay().unwrap().ending, None);
+
+        let mut resigned = record("3R 3L");
+        resigned.result = Some(GameResult::Resigned);
+        resigned.winner = Some(Colour::Black);
+        assert_eq!(
+            resigned.replay().unwrap().ending,
+            Some((GameResult::Resigned, Some(Colour::Black)))
+        );
+
+        let mut drawn = record("3R 3L");
+        drawn.result = Some(GameResult::Draw);
+        assert_eq!(
+            drawn.replay().unwrap().ending,
+            Some((GameResult::Draw, None))
+        );
+        drawn.winner = Some(Colour::Red);
+        assert_eq!(drawn.replay().unwrap_err(), RecordError::WrongResult);
+        for result in [
+            GameResult::Resigned,
+            GameResult::Abandoned,
+            GameResult::TimedOut,
+        ] {
+            let mut unwon = record("3R 3L");
+            unwon.result = Some(result);
+            assert_eq!(unwon.replay().unwrap_err(), RecordError::WrongResult);
+        }
+
+        let mut wrong = record("0R 6R 0R 6R 0R 5L 0R");
+        wrong.result = Some(GameResult::BlackWin);
+        assert_eq!(wrong.replay().unwrap_err(), RecordError::WrongResult);
+        let mut early = record("3R");
+        early.result = Some(GameResult::RedWin);
+        assert_eq!(early.replay().unwrap_err(), RecordError::WrongResult);
+
+        assert_eq!(
+            record("0R 6R 0R 6R 0R 5L 0R 1R").replay().unwrap_err(),
+            RecordError::IllegalMove(8, MoveError::GameOver)
+        );
+        assert_eq!(
+            record("3U").replay().unwrap_err(),
+            RecordError::IllegalMove(1, MoveError::IllegalDirection(Direction::Up))
+        );
+    }
+
+    #[test]
+    fn test_reads_times() {
+        let mut game = record("3R");
+        let (created, finished) = game.times().unwrap();
+        assert_eq!(created.unwrap().to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(finished, None);
+
+        game.finished_at = Some(String::from("yesterday"));
+        assert_eq!(
+            game.times(),
+            Err(RecordError::BadValue("time", String::from("yesterday")))
+        );
+    }
+}
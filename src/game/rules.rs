@@ -0,0 +1,487 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    calculate_winner, find_win_lines, place_piece, Board, Colour, Direction, MoveError, Square,
+};
+use crate::ws::Play;
+
+/// Which rules a game is played by, chosen when the game is created.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Ruleset {
+    /// Pieces stack from either the left or the right of a row
+    #[default]
+    Stacked,
+    /// Pieces only stack from one side, like falling into a column
+    Classic,
+    /// Stacked, but a player may also pop one of their own pieces off a row's end
+    PopOut,
+    /// Stacked, but pieces may also enter a column from the top or the bottom
+    FourSided,
+}
+
+impl Ruleset {
+    pub fn rules(self) -> &'static dyn Rules {
+        match self {
+            Ruleset::Stacked => &StackedRules,
+            Ruleset::Classic => &ClassicRules,
+            Ruleset::PopOut => &PopOutRules,
+            Ruleset::FourSided => &FourSidedRules,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Ruleset::Stacked => "stacked",
+            Ruleset::Classic => "classic",
+            Ruleset::PopOut => "popout",
+            Ruleset::FourSided => "foursided",
+        }
+    }
+}
+
+impl FromStr for Ruleset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Ruleset, String> {
+        match s {
+            "stacked" => Ok(Ruleset::Stacked),
+            "classic" => Ok(Ruleset::Classic),
+            "popout" => Ok(Ruleset::PopOut),
+            "foursided" => Ok(Ruleset::FourSided),
+            _ => Err(format!("unknown ruleset {s}")),
+        }
+    }
+}
+
+/// What a ruleset decides: which moves can be made, what they do, and who has won.
+/// Whose turn it is gets checked by the board before any of this.
+pub trait Rules {
+    /// Every move the given colour could make, in a stable order.
+    fn legal_moves(&self, board: &Board, colour: Colour) -> Vec<Play>;
+
+    /// Checks that the move is allowed and makes it.
+    fn apply(&self, board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError>;
+
+    /// Who has won, knowing who made the last move.
+    fn winner(&self, board: &Board, last_mover: Option<Colour>) -> Option<Colour>;
+}
+
+pub struct StackedRules;
+
+impl Rules for StackedRules {
+    fn legal_moves(&self, board: &Board, _colour: Colour) -> Vec<Play> {
+        let mut plays = Vec::new();
+        for (row, squares) in board.squares.iter().enumerate() {
+            plays.extend(placements(row, squares, true));
+        }
+        plays
+    }
+
+    fn apply(&self, board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError> {
+        if play.pop {
+            return Err(MoveError::PopNotAllowed);
+        }
+        if play.direction.is_vertical() {
+            return Err(MoveError::IllegalDirection(play.direction));
+        }
+        place(board, colour, play)
+    }
+
+    fn winner(&self, board: &Board, _last_mover: Option<Colour>) -> Option<Colour> {
+        calculate_winner(&board.squares, board.win_length)
+    }
+}
+
+pub struct ClassicRules;
+
+impl Rules for ClassicRules {
+    fn legal_moves(&self, board: &Board, _colour: Colour) -> Vec<Play> {
+        let mut plays = Vec::new();
+        for (row, squares) in board.squares.iter().enumerate() {
+            plays.extend(placements(row, squares, false));
+        }
+        plays
+    }
+
+    fn apply(&self, board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError> {
+        if play.pop {
+            return Err(MoveError::PopNotAllowed);
+        }
+        if play.direction != Direction::Right {
+            return Err(MoveError::IllegalDirection(play.direction));
+        }
+        place(board, colour, play)
+    }
+
+    fn winner(&self, board: &Board, _last_mover: Option<Colour>) -> Option<Colour> {
+        calculate_winner(&board.squares, board.win_length)
+    }
+}
+
+pub struct PopOutRules;
+
+impl Rules for PopOutRules {
+    fn legal_moves(&self, board: &Board, colour: Colour) -> Vec<Play> {
+        let mut plays = Vec::new();
+        for (row, squares) in board.squares.iter().enumerate() {
+            plays.extend(placements(row, squares, true));
+            plays.extend(pops(row, squares, colour));
+        }
+        plays
+    }
+
+    fn apply(&self, board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError> {
+        if play.direction.is_vertical() {
+            return Err(MoveError::IllegalDirection(play.direction));
+        }
+        if play.pop {
+            pop(board, colour, play)
+        } else {
+            place(board, colour, play)
+        }
+    }
+
+    /// A pop can complete lines for both colours at once, in which case whoever
+    /// popped wins.
+    fn winner(&self, board: &Board, last_mover: Option<Colour>) -> Option<Colour> {
+        let lines = find_win_lines(&board.squares, board.win_length);
+        if lines.iter().any(|line| Some(line.colour) == last_mover) {
+            last_mover
+        } else {
+            lines.first().map(|line| line.colour)
+        }
+    }
+}
+
+pub struct FourSidedRules;
+
+impl Rules for FourSidedRules {
+    /// Every row's placements, then every column's that lands on a square none of the
+    /// rows' do.
+    fn legal_moves(&self, board: &Board, _colour: Colour) -> Vec<Play> {
+        let mut plays = Vec::new();
+        for (row, squares) in board.squares.iter().enumerate() {
+            plays.extend(placements(row, squares, true));
+        }
+        for column in 0..board.squares.first().map_or(0, |row| row.len()) {
+            let is_empty = |row: &Vec<Option<Square>>| row[column].is_none();
+            let (Some(top), Some(bottom)) = (
+                board.squares.iter().position(is_empty),
+                board.squares.iter().rposition(is_empty),
+            ) else {
+                continue;
+            };
+            if !lands_on(&board.squares[top], column) {
+                plays.push(Play {
+                    line: column,
+                    direction: Direction::Down,
+                    pop: false,
+                });
+            }
+            if bottom != top && !lands_on(&board.squares[bottom], column) {
+                plays.push(Play {
+                    line: column,
+                    direction: Direction::Up,
+                    pop: false,
+                });
+            }
+        }
+        plays
+    }
+
+    fn apply(&self, board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError> {
+        if play.pop {
+            return Err(MoveError::PopNotAllowed);
+        }
+        place(board, colour, play)
+    }
+
+    fn winner(&self, board: &Board, _last_mover: Option<Colour>) -> Option<Colour> {
+        calculate_winner(&board.squares, board.win_length)
+    }
+}
+
+/// The placements into a row that would land a piece somewhere new. When the row has
+/// a single empty square, both directions land in the same place, so only one is given.
+fn placements(row: usize, squares: &[Option<Square>], both_sides: bool) -> Vec<Play> {
+    let empty = squares.iter().filter(|square| square.is_none()).count();
+    let mut plays = Vec::new();
+    if empty > 0 {
+        plays.push(Play {
+            line: row,
+            direction: Direction::Right,
+            pop: false,
+        });
+    }
+    if both_sides && empty > 1 {
+        plays.push(Play {
+            line: row,
+            direction: Direction::Left,
+            pop: false,
+        });
+    }
+    plays
+}
+
+/// Whether a placement into the row from either side lands in the given column.
+fn lands_on(squares: &[Option<Square>], column: usize) -> bool {
+    squares.iter().position(Option::is_none) == Some(column)
+        || squares.iter().rposition(Option::is_none) == Some(column)
+}
+
+/// The pops out of either end of a row that hold one of the colour's own pieces.
+fn pops(row: usize, squares: &[Option<Square>], colour: Colour) -> Vec<Play> {
+    let is_own = |square: Option<&Option<Square>>| match square {
+        Some(Some(square)) => square.value == colour,
+        _ => false,
+    };
+    let mut plays = Vec::new();
+    if is_own(squares.first()) {
+        plays.push(Play {
+            line: row,
+            direction: Direction::Right,
+            pop: true,
+        });
+    }
+    if is_own(squares.last()) {
+        plays.push(Play {
+            line: row,
+            direction: Direction::Left,
+            pop: true,
+        });
+    }
+    plays
+}
+
+/// Places a piece into a row, or into a column for `Up` and `Down`.
+fn place(board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError> {
+    if play.direction.is_vertical() {
+        let column = play.line;
+        if column >= board.squares.first().map_or(0, |row| row.len()) {
+            return Err(MoveError::ColumnOutOfRange(column));
+        }
+        if board.squares.iter().all(|row| row[column].is_some()) {
+            return Err(MoveError::ColumnFull(column));
+        }
+        place_piece(Some(colour), play, &mut board.squares);
+        return Ok(());
+    }
+
+    let row = board
+        .squares
+        .get(play.line)
+        .ok_or(MoveError::RowOutOfRange(play.line))?;
+    if row.iter().all(|square| square.is_some()) {
+        return Err(MoveError::RowFull(play.line));
+    }
+
+    place_piece(Some(colour), play, &mut board.squares);
+    Ok(())
+}
+
+/// Takes the piece off the end of the row that `play.direction` stacks against, and
+/// slides the rest of the pieces stacked against that end along to fill the gap.
+fn pop(board: &mut Board, colour: Colour, play: &Play) -> Result<(), MoveError> {
+    let row = board
+        .squares
+        .get_mut(play.line)
+        .ok_or(MoveError::RowOutOfRange(play.line))?;
+    if pops(play.line, row, colour)
+        .iter()
+        .all(|pop| pop.direction != play.direction)
+    {
+        return Err(MoveError::CannotPop(play.line));
+    }
+
+    match play.direction {
+        Direction::Right => {
+            let stacked = row.iter().take_while(|square| square.is_some()).count();
+            row[..stacked].rotate_left(1);
+            row[stacked - 1] = None;
+        }
+        Direction::Left => {
+            let stacked = row
+                .iter()
+                .rev()
+                .take_while(|square| square.is_some())
+                .count();
+            let start = row.len() - stacked;
+            row[start..].rotate_right(1);
+            row[start] = None;
+        }
+        Direction::Up | Direction::Down => return Err(MoveError::IllegalDirection(play.direction)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameSettings, MoveOutcome, Squares};
+
+    const B: Option<Square> = Some(Square {
+        value: Colour::Black,
+        direction: Direction::Right,
+    });
+    const R: Option<Square> = Some(Square {
+        value: Colour::Red,
+        direction: Direction::Right,
+    });
+
+    fn play(row: usize, direction: Direction, pop: bool) -> Play {
+        Play {
+            line: row,
+            direction,
+            pop,
+        }
+    }
+
+    #[test]
+    fn test_classic_only_stacks_from_one_side() {
+        let mut board = Board::empty(&GameSettings {
+            ruleset: Ruleset::Classic,
+            ..GameSettings::default()
+        });
+        assert!(board
+            .legal_moves()
+            .iter()
+            .all(|play| play.direction == Direction::Right && !play.pop));
+        assert_eq!(
+            board.play(Colour::Red, &play(0, Direction::Left, false)),
+            Err(MoveError::IllegalDirection(Direction::Left))
+        );
+        assert_eq!(
+            board.play(Colour::Red, &play(0, Direction::Right, true)),
+            Err(MoveError::PopNotAllowed)
+        );
+        assert_eq!(
+            board.play(Colour::Red, &play(0, Direction::Right, false)),
+            Ok(MoveOutcome::Continue(Colour::Black))
+        );
+    }
+
+    #[test]
+    fn test_four_sided_enters_columns() {
+        let settings = GameSettings {
+            rows: 4,
+            columns: 5,
+            ruleset: Ruleset::FourSided,
+            ..GameSettings::default()
+        };
+        let mut stacked = Board::empty(&GameSettings {
+            ruleset: Ruleset::Stacked,
+            ..settings
+        });
+        assert_eq!(
+            stacked.play(Colour::Red, &play(0, Direction::Down, false)),
+            Err(MoveError::IllegalDirection(Direction::Down))
+        );
+
+        let mut board = Board::empty(&settings);
+        // 4 rows and 5 columns, two ways into each, less the corners that a row and a
+        // column both reach
+        assert_eq!(board.legal_moves().len(), 14);
+        assert_eq!(
+            board.play(Colour::Red, &play(5, Direction::Down, false)),
+            Err(MoveError::ColumnOutOfRange(5))
+        );
+        for (colour, direction) in [
+            (Colour::Red, Direction::Down),
+            (Colour::Black, Direction::Up),
+            (Colour::Red, Direction::Down),
+        ] {
+            board.play(colour, &play(4, direction, false)).unwrap();
+        }
+        let column: Vec<_> = board.squares().iter().map(|row| row[4]).collect();
+        assert_eq!(
+            column,
+            vec![
+                Some(Square {
+                    value: Colour::Red,
+                    direction: Direction::Down
+                }),
+                Some(Square {
+                    value: Colour::Red,
+                    direction: Direction::Down
+                }),
+                None,
+                Some(Square {
+                    value: Colour::Black,
+                    direction: Direction::Up
+                }),
+            ]
+        );
+        // The last square can be reached from either end of the column or from its row,
+        // so it's only offered once, as the row's placement
+        let plays = board.legal_moves();
+        assert!(plays.contains(&play(2, Direction::Left, false)));
+        assert!(!plays
+            .iter()
+            .any(|play| play.line == 4 && play.direction.is_vertical()));
+
+        board
+            .play(Colour::Black, &play(4, Direction::Up, false))
+            .unwrap();
+        assert_eq!(
+            board.play(Colour::Red, &play(4, Direction::Down, false)),
+            Err(MoveError::ColumnFull(4))
+        );
+    }
+
+    #[test]
+    fn test_pop_slides_the_row() {
+        let u: Option<Square> = None;
+        let squares: Squares = vec![
+            vec![R, B, R, u, u, u, B],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+        ];
+        let mut board = Board::new(squares, 4, Ruleset::PopOut);
+
+        assert_eq!(
+            board.play(Colour::Red, &play(0, Direction::Left, true)),
+            Err(MoveError::CannotPop(0))
+        );
+        assert_eq!(
+            board.play(Colour::Red, &play(1, Direction::Right, true)),
+            Err(MoveError::CannotPop(1))
+        );
+        assert_eq!(
+            board.play(Colour::Red, &play(0, Direction::Right, true)),
+            Ok(MoveOutcome::Continue(Colour::Black))
+        );
+        assert_eq!(board.squares()[0], vec![B, R, u, u, u, u, B]);
+        assert_eq!(board.ply(), 5);
+
+        assert_eq!(
+            board.play(Colour::Black, &play(0, Direction::Left, true)),
+            Ok(MoveOutcome::Continue(Colour::Red))
+        );
+        assert_eq!(board.squares()[0], vec![B, R, u, u, u, u, u]);
+    }
+
+    #[test]
+    fn test_popper_wins_double_line() {
+        let u: Option<Square> = None;
+        let squares: Squares = vec![
+            vec![B, R, B, u, u, u, u],
+            vec![R, B, u, u, u, u, u],
+            vec![R, B, u, u, u, u, u],
+            vec![R, B, u, u, u, u, u],
+            vec![u, u, u, u, u, u, u],
+        ];
+        let mut board = Board::new(squares, 4, Ruleset::PopOut);
+        assert_eq!(board.current_player(), Some(Colour::Black));
+
+        assert_eq!(
+            board.play(Colour::Black, &play(0, Direction::Right, true)),
+            Ok(MoveOutcome::Win(Colour::Black))
+        );
+        assert_eq!(board.win_lines().len(), 2);
+        assert_eq!(board.winner(), Some(Colour::Black));
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::ai::{ordered_moves, Search, WIN_SCORE};
+use super::bitboard::Position;
+use super::{Board, Colour, MoveOutcome};
+use crate::ws::Play;
+
+/// No game can last longer than this many moves from any position we'd be asked about,
+/// except pop-out games, which are cut off here.
+const MAX_DEPTH: usize = 100;
+
+/// How many positions the transposition table holds before it's cleared out.
+const TABLE_SIZE: usize = 1 << 20;
+
+/// How many positions are searched between checks of the clock, a power of two.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// How good a position is for whoever's turn it is, and how good each of their moves
+/// is. Scores above the heuristic's range are forced wins, and below it forced losses.
+#[derive(Serialize, Debug)]
+pub struct Analysis {
+    pub current_player: Option<Colour>,
+    pub evaluation: i32,
+    /// How many moves ahead were searched
+    pub depth: usize,
+    /// Whether the search saw every position through to the end of the game, so the
+    /// scores are exact rather than guesses
+    pub solved: bool,
+    pub moves: Vec<MoveScore>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MoveScore {
+    #[serde(flatten)]
+    pub play: Play,
+    pub score: i32,
+}
+
+#[derive(Clone, Copy)]
+enum Bound {
+    Exact,
+    /// The real score is at least this
+    Lower,
+    /// The real score is at most this
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    depth: usize,
+    score: i32,
+    bound: Bound,
+    /// Whether the score came from the heuristic anywhere below this position
+    horizon: bool,
+}
+
+struct Solver {
+    search: Search,
+    table: HashMap<Position, Entry>,
+    deadline: Option<Instant>,
+    nodes: u64,
+    horizon: bool,
+}
+
+/// Searches deeper and deeper with negamax until the time budget runs out or the
+/// position is solved, and reports the scores from the deepest search that finished.
+/// The first search always finishes, however long it takes.
+pub fn analyse(board: &Board, budget: Duration) -> Analysis {
+    deepen(board, MAX_DEPTH, budget)
+}
+
+/// Like `analyse`, but never searches more than `max_depth` moves ahead.
+pub(super) fn deepen(board: &Board, max_depth: usize, budget: Duration) -> Analysis {
+    let deadline = Instant::now() + budget;
+    let position = Position::from_board(board);
+    let Some(colour) = position.current_player() else {
+        return Analysis {
+            current_player: None,
+            evaluation: 0,
+            depth: 0,
+            solved: true,
+            moves: Vec::new(),
+        };
+    };
+
+    let mut solver = Solver {
+        search: Search {
+            lines: position.lines(),
+        },
+        table: HashMap::new(),
+        deadline: None,
+        nodes: 0,
+        horizon: false,
+    };
+    let plays = ordered_moves(&position);
+    let mut analysis = None;
+    for depth in 1..=max_depth.max(1) {
+        solver.horizon = false;
+        let Some(scores) = solver.score_moves(&position, colour, &plays, depth) else {
+            break;
+        };
+        let solved = !solver.horizon;
+        analysis = Some(Analysis {
+            current_player: Some(colour),
+            evaluation: scores.iter().copied().max().unwrap_or(0),
+            depth,
+            solved,
+            moves: plays
+                .iter()
+                .zip(scores)
+                .map(|(&play, score)| MoveScore { play, score })
+                .collect(),
+        });
+        if solved || Instant::now() >= deadline {
+            break;
+        }
+        solver.deadline = Some(deadline);
+    }
+    analysis.expect("the first search always finishes")
+}
+
+impl Solver {
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        self.nodes & (CLOCK_INTERVAL - 1) == 0
+            && matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    /// The exact score of every move at the given depth, or `None` if time ran out.
+    fn score_moves(
+        &mut self,
+        position: &Position,
+        colour: Colour,
+        plays: &[Play],
+        depth: usize,
+    ) -> Option<Vec<i32>> {
+        plays
+            .iter()
+            .map(|play| {
+                self.score_move(position, colour, play, depth, -WIN_SCORE * 2, WIN_SCORE * 2)
+            })
+            .collect()
+    }
+
+    fn score_move(
+        &mut self,
+        position: &Position,
+        colour: Colour,
+        play: &Play,
+        depth: usize,
+        alpha: i32,
+        beta: i32,
+    ) -> Option<i32> {
+        let mut next = *position;
+        Some(match next.play(colour, play) {
+            Ok(MoveOutcome::Win(winner)) if winner == colour => WIN_SCORE + depth as i32,
+            Ok(MoveOutcome::Win(_)) => -WIN_SCORE - depth as i32,
+            Ok(MoveOutcome::Draw) => 0,
+            Ok(MoveOutcome::Continue(other)) => {
+                -self.negamax(&next, other, depth - 1, -beta, -alpha)?
+            }
+            // Only legal moves are ever tried
+            Err(_) => unreachable!("solver tried an illegal move"),
+        })
+    }
+
+    fn negamax(
+        &mut self,
+        position: &Position,
+        colour: Colour,
+        depth: usize,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> Option<i32> {
+        if self.out_of_time() {
+            return None;
+        }
+        if depth == 0 {
+            self.horizon = true;
+            return Some(self.search.evaluate(position, colour));
+        }
+
+        let original_alpha = alpha;
+        if let Some(entry) = self.table.get(position).copied() {
+            if entry.depth >= depth {
+                self.horizon |= entry.horizon;
+                match entry.bound {
+                    Bound::Exact => return Some(entry.score),
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                }
+                if alpha >= beta {
+                    return Some(entry.score);
+                }
+            }
+        }
+
+        let horizon = std::mem::replace(&mut self.horizon, false);
+        let mut best = -WIN_SCORE * 2;
+        for play in ordered_moves(position) {
+            let score = self.score_move(position, colour, &play, depth, alpha, beta)?;
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        if self.table.len() >= TABLE_SIZE {
+            self.table.clear();
+        }
+        let bound = if best <= original_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert(
+            *position,
+            Entry {
+                depth,
+                score: best,
+                bound,
+                horizon: self.horizon,
+            },
+        );
+        self.horizon |= horizon;
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::notation::Moves;
+    use crate::game::{Direction, GameSettings};
+
+    fn play_all(board: &mut Board, moves: &str) {
+        let moves: Moves = moves.parse().unwrap();
+        for play in &moves.0 {
+            let colour = board.current_player().unwrap();
+            board.play(colour, play).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_finds_the_win() {
+        let mut board = Board::empty(&GameSettings::default());
+        play_all(&mut board, "0R 6R 0R 6R 0R 5L");
+
+        let analysis = analyse(&board, Duration::from_millis(100));
+        assert_eq!(analysis.current_player, Some(Colour::Red));
+        assert!(analysis.evaluation > WIN_SCORE);
+        assert_eq!(analysis.moves.len(), board.legal_moves().len());
+        let winning: Vec<_> = analysis
+            .moves
+            .iter()
+            .filter(|score| score.score == analysis.evaluation)
+            .collect();
+        assert_eq!(winning.len(), 1);
+        assert_eq!(winning[0].play.line, 0);
+        assert_eq!(winning[0].play.direction, Direction::Right);
+    }
+
+    #[test]
+    fn test_solves_small_board() {
+        let settings = GameSettings {
+            rows: 4,
+            columns: 4,
+            win_length: 3,
+            ..GameSettings::default()
+        };
+        let mut board = Board::empty(&settings);
+        play_all(&mut board, "1R 2R 1L 2L 0R 3L 0L 3R");
+
+        let analysis = analyse(&board, Duration::from_secs(60));
+        assert!(analysis.solved);
+        assert!(analysis.evaluation.abs() > WIN_SCORE || analysis.evaluation == 0);
+    }
+
+    #[test]
+    fn test_finished_game() {
+        let mut board = Board::empty(&GameSettings::default());
+        play_all(&mut board, "0R 6R 0R 6R 0R 5L 0R");
+
+        let analysis = analyse(&board, Duration::from_millis(100));
+        assert_eq!(analysis.current_player, None);
+        assert!(analysis.moves.is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! Undoing moves by agreement. A player asks to take back their last move, and the
+//! other player accepts or declines. Taking back a move also takes back every move
+//! played after it, so a player can still take back a move once it's been answered.
+
+use serde::Deserialize;
+
+use super::MoveError;
+use crate::db::entities::{game, game_move};
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Takeback {
+    Request,
+    Accept,
+    Decline,
+}
+
+/// Records a takeback being asked for or answered. Once one's agreed, returns the ply
+/// of the move being taken back, which the game is rolled back to before.
+pub(super) fn answer_takeback(
+    game: &mut game::Model,
+    player_id: i32,
+    takeback: Takeback,
+    is_ai_game: bool,
+    history: &[game_move::Model],
+) -> Result<Option<usize>, MoveError> {
+    let requester = match takeback {
+        // The AI never minds
+        Takeback::Request if is_ai_game => player_id,
+        Takeback::Request => {
+            last_move_by(history, player_id)?;
+            game.takeback_by = Some(player_id);
+            return Ok(None);
+        }
+        Takeback::Accept | Takeback::Decline => match game.takeback_by {
+            Some(requester) if requester != player_id => requester,
+            _ => return Err(MoveError::NoTakebackToAnswer),
+        },
+    };
+    game.takeback_by = None;
+    match takeback {
+        Takeback::Decline => Ok(None),
+        _ => last_move_by(history, requester).map(Some),
+    }
+}
+
+fn last_move_by(history: &[game_move::Model], player_id: i32) -> Result<usize, MoveError> {
+    history
+        .iter()
+        .rev()
+        .find(|record| record.player_id == player_id)
+        .map(|record| record.ply as usize)
+        .ok_or(MoveError::NothingToTakeBack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{start_game, GameStore, MemoryStore, NewMove};
+
+    async fn game_with_moves(plays: &[&str]) -> (game::Model, Vec<game_move::Model>) {
+        let db = MemoryStore::new();
+        let game = start_game(&db, "alice", "bob").await;
+        let moves: Vec<NewMove> = plays
+            .iter()
+            .enumerate()
+            .map(|(i, play)| {
+                let player = [game.player_red_id, game.player_black_id][i % 2].unwrap();
+                (i + 1, player, play.parse().unwrap())
+            })
+            .collect();
+        db.save_game_and_moves(game.clone(), None, &moves)
+            .await
+            .unwrap();
+        let game = db.find_game(game.id).await.unwrap().unwrap();
+        let history = db.get_moves(game.id).await.unwrap();
+        (game, history)
+    }
+
+    #[tokio::test]
+    async fn test_takes_back_once_accepted() {
+        let (mut game, history) = game_with_moves(&["3R", "3L", "2R"]).await;
+        let alice = game.player_red_id.unwrap();
+        let bob = game.player_black_id.unwrap();
+
+        assert_eq!(
+            answer_takeback(&mut game, bob, Takeback::Request, false, &history),
+            Ok(None)
+        );
+        assert_eq!(game.takeback_by, Some(bob));
+        assert_eq!(
+            answer_takeback(&mut game, bob, Takeback::Accept, false, &history),
+            Err(MoveError::NoTakebackToAnswer)
+        );
+        // Bob's last move was the second, so the third goes too
+        assert_eq!(
+            answer_takeback(&mut game, alice, Takeback::Accept, false, &history),
+            Ok(Some(2))
+        );
+        assert_eq!(game.takeback_by, None);
+    }
+
+    #[tokio::test]
+    async fn test_declined_takeback() {
+        let (mut game, history) = game_with_moves(&["3R"]).await;
+        let alice = game.player_red_id.unwrap();
+        let bob = game.player_black_id.unwrap();
+
+        assert_eq!(
+            answer_takeback(&mut game, bob, Takeback::Request, false, &history),
+            Err(MoveError::NothingToTakeBack)
+        );
+        answer_takeback(&mut game, alice, Takeback::Request, false, &history).unwrap();
+        assert_eq!(
+            answer_takeback(&mut game, bob, Takeback::Decline, false, &history),
+            Ok(None)
+        );
+        assert_eq!(game.takeback_by, None);
+        assert_eq!(
+            answer_takeback(&mut game, bob, Takeback::Accept, false, &history),
+            Err(MoveError::NoTakebackToAnswer)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ai_takes_back_straight_away() {
+        let (mut game, history) = game_with_moves(&["3R", "3L"]).await;
+        let alice = game.player_red_id.unwrap();
+
+        assert_eq!(
+            answer_takeback(&mut game, alice, Takeback::Request, true, &history),
+            Ok(Some(1))
+        );
+        assert_eq!(game.takeback_by, None);
+    }
+}
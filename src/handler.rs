@@ -1,14 +1,30 @@
 use std::collections::HashSet;
+use std::time::Duration;
 
-use crate::{ws, Client, Clients, Db, Result, Sockets};
+use crate::db::{DbError, NewMove, PlayerStats};
+use crate::game::notation::Moves;
+use crate::game::record::{GameRecord, RecordError, Replay, Times};
+use crate::game::rules::Ruleset;
+use crate::game::{ai::Difficulty, player_name, solver, Board, Colour, GameSettings, Squares};
+use crate::ws::Play;
+use crate::{lobby, ws, Client, Clients, Db, Result, Sockets};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::{env, fs};
 use uuid::Uuid;
-use warp::{http::Response, http::StatusCode, reply::json, Reply};
+use warp::hyper::body::Bytes;
+use warp::reply::{json, with_header, with_status};
+use warp::{http::Response, http::StatusCode, Reply};
 
 #[derive(Deserialize, Debug)]
 pub struct RegisterRequest {
     username: String,
+    /// Board settings for any new game this player starts
+    #[serde(flatten)]
+    settings: GameSettings,
+    /// How strong the computer opponent is in AI games
+    #[serde(default)]
+    difficulty: Difficulty,
 }
 
 #[derive(Serialize, Debug)]
@@ -22,29 +38,46 @@ pub async fn register_handler(
     sockets: Sockets,
     db: Db,
 ) -> Result<impl Reply> {
+    if !body.settings.is_valid() {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    }
     let username = body.username;
-    let player = db.write().await.get_player(&username).await;
-    let uuid = Uuid::new_v4().as_simple().to_string();
-    register_client(username.clone(), player.id, uuid.clone(), clients, sockets).await;
-    let protocol;
-    let base_url = match env::var("STACKED_FOURSIDE_HOST") {
-        Ok(val) => {
-            protocol = "wss";
-            val
-        }
-        _ => {
-            protocol = "ws";
-            String::from("127.0.0.1:4321")
-        }
+    let player = match db.get_player(&username).await {
+        Ok(player) => player,
+        Err(err) => return Ok(server_error(err)),
     };
+    let uuid = Uuid::new_v4().as_simple().to_string();
+    register_client(
+        username.clone(),
+        player.id,
+        body.settings,
+        body.difficulty,
+        uuid.clone(),
+        clients,
+        sockets,
+    )
+    .await;
+    let (secure, base_url) = base_url();
+    let protocol = if secure { "wss" } else { "ws" };
     Ok(json(&RegisterResponse {
         url: format!("{protocol}://{base_url}/ws/{uuid}"),
-    }))
+    })
+    .into_response())
+}
+
+/// Where the game is being served from, and whether it's over TLS.
+fn base_url() -> (bool, String) {
+    match env::var("STACKED_FOURSIDE_HOST") {
+        Ok(val) => (true, val),
+        _ => (false, String::from("127.0.0.1:4321")),
+    }
 }
 
 async fn register_client(
     username: String,
     user_id: i32,
+    settings: GameSettings,
+    difficulty: Difficulty,
     uuid: String,
     clients: Clients,
     sockets: Sockets,
@@ -54,6 +87,8 @@ async fn register_client(
         Client {
             username,
             user_id,
+            settings,
+            difficulty,
             sender: None,
         },
     );
@@ -66,8 +101,9 @@ pub async fn unregister_handler(
     uuid: String,
     clients: Clients,
     sockets: Sockets,
+    db: Db,
 ) -> Result<impl Reply> {
-    ws::remove_socket(&uuid, clients, sockets).await;
+    ws::remove_socket(&uuid, clients, sockets, db).await;
     Ok(StatusCode::OK)
 }
 
@@ -87,6 +123,329 @@ pub async fn ws_handler(
     }
 }
 
+/// The longest a client may ask the solver to think for.
+const MAX_ANALYSIS_TIME: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum AnalysisTarget {
+    Game {
+        game_id: i32,
+    },
+    Position {
+        squares: Squares,
+        win_length: usize,
+        #[serde(default)]
+        ruleset: Ruleset,
+        /// How many moves have been made, if pieces have been popped
+        ply: Option<usize>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnalysisRequest {
+    #[serde(flatten)]
+    target: AnalysisTarget,
+    /// How long the solver may think for, in milliseconds
+    #[serde(default = "default_analysis_time")]
+    time_ms: u64,
+}
+
+fn default_analysis_time() -> u64 {
+    1000
+}
+
+pub async fn analysis_handler(body: AnalysisRequest, db: Db) -> Result<impl Reply> {
+    let board = match body.target {
+        AnalysisTarget::Game { game_id } => match db.find_game(game_id).await {
+            Ok(Some(game)) => match game.board() {
+                Ok(board) => board,
+                Err(err) => return Ok(server_error(err)),
+            },
+            Ok(None) => return Ok(StatusCode::NOT_FOUND.into_response()),
+            Err(err) => return Ok(server_error(err)),
+        },
+        AnalysisTarget::Position {
+            squares,
+            win_length,
+            ruleset,
+            ply,
+        } => {
+            let settings = GameSettings {
+                rows: squares.len(),
+                columns: squares.first().map_or(0, |row| row.len()),
+                win_length,
+                ruleset,
+            };
+            if !settings.is_valid() || squares.iter().any(|row| row.len() != settings.columns) {
+                return Ok(StatusCode::BAD_REQUEST.into_response());
+            }
+            let board = Board::new(squares, win_length, ruleset);
+            match ply {
+                Some(ply) => board.with_ply(ply),
+                None => board,
+            }
+        }
+    };
+
+    let budget = Duration::from_millis(body.time_ms).min(MAX_ANALYSIS_TIME);
+    match tokio::task::spawn_blocking(move || solver::analyse(&board, budget)).await {
+        Ok(analysis) => Ok(json(&analysis).into_response()),
+        Err(err) => {
+            eprintln!("analysis failed: {}", err);
+            Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistoryResponse {
+    game_id: i32,
+    /// The whole game in move notation
+    moves: String,
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistoryEntry {
+    ply: i32,
+    player_id: i32,
+    #[serde(flatten)]
+    play: Play,
+    played_at: String,
+}
+
+pub async fn history_handler(game_id: i32, db: Db) -> Result<impl Reply> {
+    match db.find_game(game_id).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(err) => return Ok(server_error(err)),
+    }
+    let moves = match db.get_moves(game_id).await {
+        Ok(moves) => moves,
+        Err(err) => return Ok(server_error(err)),
+    };
+    let history: Vec<HistoryEntry> = moves
+        .iter()
+        .map(|record| HistoryEntry {
+            ply: record.ply,
+            player_id: record.player_id,
+            play: record.play(),
+            played_at: record.played_at.to_rfc3339(),
+        })
+        .collect();
+    Ok(json(&HistoryResponse {
+        game_id,
+        moves: Moves(history.iter().map(|entry| entry.play).collect()).to_string(),
+        history,
+    })
+    .into_response())
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Text,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+pub async fn export_handler(game_id: i32, query: ExportQuery, db: Db) -> Result<impl Reply> {
+    let record = match game_record(game_id, &db).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(err) => return Ok(server_error(err)),
+    };
+    Ok(match query.format {
+        ExportFormat::Json => json(&record).into_response(),
+        ExportFormat::Text => with_header(
+            record.to_string(),
+            "content-type",
+            "text/plain; charset=utf-8",
+        )
+        .into_response(),
+    })
+}
+
+async fn game_record(game_id: i32, db: &Db) -> std::result::Result<Option<GameRecord>, DbError> {
+    let Some(game) = db.find_game(game_id).await? else {
+        return Ok(None);
+    };
+    let moves = db.get_moves(game_id).await?;
+    Ok(Some(GameRecord {
+        red: player_name(game.player_red_id, db).await?,
+        black: player_name(game.player_black_id, db).await?,
+        settings: game.settings(),
+        result: game.result(),
+        winner: match game.winner_id {
+            Some(winner) if game.player_red_id == Some(winner) => Some(Colour::Red),
+            Some(_) => Some(Colour::Black),
+            None => None,
+        },
+        moves: Moves(moves.iter().map(|record| record.play()).collect()).to_string(),
+        created_at: Some(game.created_at.to_rfc3339()),
+        updated_at: Some(game.updated_at.to_rfc3339()),
+        finished_at: game.finished_at.map(|time| time.to_rfc3339()),
+    }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportResponse {
+    game_id: i32,
+}
+
+/// Stores a game record, sent as JSON or as text, as a game for analysis once its moves
+/// have been checked by playing them through.
+pub async fn import_handler(
+    content_type: Option<String>,
+    body: Bytes,
+    db: Db,
+) -> Result<impl Reply> {
+    let record = match read_record(content_type.as_deref(), &body) {
+        Ok(record) => record,
+        Err(err) => return Ok(with_status(err, StatusCode::BAD_REQUEST).into_response()),
+    };
+    let replay = match record.replay() {
+        Ok(replay) => replay,
+        Err(err) => {
+            return Ok(with_status(err.to_string(), StatusCode::BAD_REQUEST).into_response())
+        }
+    };
+    let (red, black) = match record.players() {
+        Ok(players) => players,
+        Err(err) => {
+            return Ok(with_status(err.to_string(), StatusCode::BAD_REQUEST).into_response())
+        }
+    };
+    let times = match record.times() {
+        Ok(times) => times,
+        Err(err) => {
+            return Ok(with_status(err.to_string(), StatusCode::BAD_REQUEST).into_response())
+        }
+    };
+
+    match store_record(&record.settings, replay, red, black, times, &db).await {
+        Ok(game_id) => {
+            Ok(with_status(json(&ImportResponse { game_id }), StatusCode::CREATED).into_response())
+        }
+        Err(err) => Ok(server_error(err)),
+    }
+}
+
+/// Reads a game record as text if it's sent as text, and otherwise as JSON.
+fn read_record(content_type: Option<&str>, body: &[u8]) -> std::result::Result<GameRecord, String> {
+    match content_type {
+        Some(content_type) if content_type.starts_with("text/") => std::str::from_utf8(body)
+            .map_err(|err| err.to_string())?
+            .parse()
+            .map_err(|err: RecordError| err.to_string()),
+        _ => serde_json::from_slice(body).map_err(|err| err.to_string()),
+    }
+}
+
+/// Stores a checked game record, as played when the record says it was if it does,
+/// rather than when it was imported.
+async fn store_record(
+    settings: &GameSettings,
+    replay: Replay,
+    red: &str,
+    black: &str,
+    (created_at, finished_at): Times,
+    db: &Db,
+) -> std::result::Result<i32, DbError> {
+    let red = db.get_player(red).await?.id;
+    let black = db.get_player(black).await?.id;
+    let mut game = db
+        .create_analysis_game(red, black, settings, created_at.unwrap_or_else(Utc::now))
+        .await?;
+    let board = &replay.board;
+    game.squares = serde_json::to_value(board.squares()).unwrap();
+    game.ply = board.ply() as i32;
+    if let Some((result, winner)) = replay.ending {
+        game.finish(result, winner);
+        if let Some(finished_at) = finished_at {
+            game.finished_at = Some(finished_at.into());
+        }
+    }
+    let game_id = game.id;
+    let moves: Vec<NewMove> = replay
+        .moves
+        .0
+        .into_iter()
+        .enumerate()
+        .map(|(i, play)| (i + 1, if i % 2 == 0 { red } else { black }, play))
+        .collect();
+    db.save_game_and_moves(game, None, &moves).await?;
+    Ok(game_id)
+}
+
+pub async fn stats_handler(username: String, db: Db) -> Result<impl Reply> {
+    let player = match db.find_player(&username).await {
+        Ok(Some(player)) => player,
+        Ok(None) => return Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(err) => return Ok(server_error(err)),
+    };
+    match db.get_finished_games(player.id).await {
+        Ok(games) => Ok(json(&PlayerStats::from_games(player.id, &games)).into_response()),
+        Err(err) => Ok(server_error(err)),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InviteRequest {
+    username: String,
+    /// The board for the private game
+    #[serde(flatten)]
+    settings: GameSettings,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InviteResponse {
+    code: String,
+    /// A link to send to a friend, which joins the game when opened
+    url: String,
+    expires_at: String,
+}
+
+/// Opens a private game that's left out of the lobby, for a friend to join with the code.
+pub async fn invite_handler(body: InviteRequest, db: Db) -> Result<impl Reply> {
+    if !body.settings.is_valid() {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    }
+    let player = match db.get_player(&body.username).await {
+        Ok(player) => player,
+        Err(err) => return Ok(server_error(err)),
+    };
+    let invite = match lobby::create_invite(&db, player.id, &body.settings).await {
+        Ok(invite) => invite,
+        Err(err) => return Ok(server_error(err)),
+    };
+    let (secure, base_url) = base_url();
+    let protocol = if secure { "https" } else { "http" };
+    Ok(with_status(
+        json(&InviteResponse {
+            url: format!("{protocol}://{base_url}/?invite={}", invite.code),
+            code: invite.code,
+            expires_at: invite.expires_at.to_rfc3339(),
+        }),
+        StatusCode::CREATED,
+    )
+    .into_response())
+}
+
+/// Logs what went wrong with the store, and tells the client only that it wasn't their
+/// fault.
+fn server_error(err: DbError) -> warp::reply::Response {
+    eprintln!("store error: {}", err);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
 pub async fn health_handler() -> Result<impl Reply> {
     Ok(Response::builder()
         .status(StatusCode::OK)
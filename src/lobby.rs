@@ -0,0 +1,248 @@
+//! Where players who aren't in a game find one. A player posts an open seek for a game
+//! on the board they'd like, which stays up until they leave or it goes stale. Every
+//! connected client is sent the list of seeks whenever it changes, and accepting a seek
+//! starts the game for both players. To play someone in particular instead, a player
+//! makes an invite, which is left out of the seeks and can only be joined with its code.
+
+use std::fmt;
+
+use chrono::{Duration, Utc};
+use rand::Rng;
+use serde::Serialize;
+use warp::ws::Message;
+
+use crate::db::entities::invite;
+use crate::db::DbError;
+use crate::game::{play_piece, send_error_frame, GameSettings, SERVER_ERROR};
+use crate::{env_minutes, Client, Clients, Db, Sockets};
+
+/// Letters and digits for invite codes, leaving out ones that are easily mistaken for
+/// each other.
+const INVITE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LENGTH: usize = 6;
+/// How many codes to try before giving up on an invite, should they clash with open ones.
+const INVITE_CODE_ATTEMPTS: usize = 5;
+/// How long an invite stays open if `INVITE_EXPIRY_MINUTES` isn't set.
+const DEFAULT_INVITE_EXPIRY_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone)]
+pub enum LobbyAction {
+    /// Post a seek, taking down any the player already has up
+    Seek(GameSettings),
+    CancelSeek(i32),
+    AcceptSeek(i32),
+    /// Join a private game by its invite code
+    Join(String),
+}
+
+#[derive(Debug)]
+pub enum LobbyError {
+    InvalidSettings,
+    SeekGone(i32),
+    OwnSeek,
+    InviteGone(String),
+    OwnInvite,
+    Db(DbError),
+}
+
+impl fmt::Display for LobbyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LobbyError::InvalidSettings => write!(f, "the board settings are not valid"),
+            LobbyError::SeekGone(id) => write!(f, "seek {id} is no longer open"),
+            LobbyError::OwnSeek => write!(f, "you cannot accept your own seek"),
+            LobbyError::InviteGone(code) => {
+                write!(f, "invite {code} has expired or has already been used")
+            }
+            LobbyError::OwnInvite => write!(f, "you cannot join your own invite"),
+            LobbyError::Db(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for LobbyError {}
+
+impl From<DbError> for LobbyError {
+    fn from(err: DbError) -> LobbyError {
+        LobbyError::Db(err)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SeeksFrame {
+    seeks: Vec<SeekEntry>,
+}
+
+#[derive(Serialize, Debug)]
+struct SeekEntry {
+    id: i32,
+    player: String,
+    #[serde(flatten)]
+    settings: GameSettings,
+    created_at: String,
+}
+
+/// Carries out a lobby request, and sends everyone the seeks as they are afterwards.
+pub async fn lobby_action(
+    client: &Client,
+    clients: &Clients,
+    sockets: &Sockets,
+    action: LobbyAction,
+    db: &Db,
+) {
+    let outcome = match try_lobby_action(client, clients, sockets, &action, db).await {
+        Ok(()) => broadcast_seeks(clients, db).await.map_err(LobbyError::from),
+        Err(err) => Err(err),
+    };
+    match outcome {
+        Ok(()) => (),
+        Err(LobbyError::Db(err)) => {
+            eprintln!("error in the lobby for {}: {}", client.username, err);
+            send_error_frame(client, None, SERVER_ERROR);
+        }
+        Err(err) => {
+            println!("Rejecting {:?} from {}: {}", action, client.username, err);
+            send_error_frame(client, None, &err.to_string());
+        }
+    }
+}
+
+async fn try_lobby_action(
+    client: &Client,
+    clients: &Clients,
+    sockets: &Sockets,
+    action: &LobbyAction,
+    db: &Db,
+) -> Result<(), LobbyError> {
+    match *action {
+        LobbyAction::Seek(settings) => {
+            if !settings.is_valid() {
+                return Err(LobbyError::InvalidSettings);
+            }
+            let seek = db.create_seek(client.user_id, &settings).await?;
+            println!("{} posted seek {}", client.username, seek.id);
+        }
+        LobbyAction::CancelSeek(seek_id) => {
+            if !db.cancel_seek(seek_id, client.user_id).await? {
+                return Err(LobbyError::SeekGone(seek_id));
+            }
+            println!("{} cancelled seek {}", client.username, seek_id);
+        }
+        LobbyAction::AcceptSeek(seek_id) => {
+            match db.find_seek(seek_id).await? {
+                None => return Err(LobbyError::SeekGone(seek_id)),
+                Some(seek) if seek.player_id == client.user_id => return Err(LobbyError::OwnSeek),
+                Some(_) => (),
+            }
+            let Some(game) = db.accept_seek(seek_id, client.user_id).await? else {
+                return Err(LobbyError::SeekGone(seek_id));
+            };
+            println!(
+                "{} accepted seek {}, starting game {}",
+                client.username, seek_id, game.id
+            );
+            // Sends the new game to both players
+            play_piece(client, clients, sockets, Some(game.id), None, db).await;
+        }
+        LobbyAction::Join(ref code) => {
+            let code = code.trim().to_uppercase();
+            match db.find_invite(&code).await? {
+                None => return Err(LobbyError::InviteGone(code)),
+                Some(invite) if invite.player_id == client.user_id => {
+                    return Err(LobbyError::OwnInvite)
+                }
+                Some(_) => (),
+            }
+            let Some(game) = db.join_invite(&code, client.user_id).await? else {
+                return Err(LobbyError::InviteGone(code));
+            };
+            println!(
+                "{} joined invite {}, starting game {}",
+                client.username, code, game.id
+            );
+            play_piece(client, clients, sockets, Some(game.id), None, db).await;
+        }
+    }
+    Ok(())
+}
+
+/// Opens an invite under a fresh code, drawing another while it clashes with an open one.
+pub async fn create_invite(
+    db: &Db,
+    player_id: i32,
+    settings: &GameSettings,
+) -> Result<invite::Model, DbError> {
+    let expires_at = Utc::now() + invite_expiry();
+    let mut attempts = 1;
+    loop {
+        match db
+            .create_invite(player_id, &invite_code(), settings, expires_at)
+            .await
+        {
+            Err(DbError::CodeTaken(_)) if attempts < INVITE_CODE_ATTEMPTS => attempts += 1,
+            result => return result,
+        }
+    }
+}
+
+/// A fresh, short code for an invite.
+fn invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_ALPHABET[rng.gen_range(0..INVITE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// How long invites stay open for nobody to join them, from `INVITE_EXPIRY_MINUTES`.
+fn invite_expiry() -> Duration {
+    env_minutes("INVITE_EXPIRY_MINUTES", DEFAULT_INVITE_EXPIRY_MINUTES)
+}
+
+async fn seeks_payload(db: &Db) -> Result<String, DbError> {
+    let seeks = db
+        .get_seeks()
+        .await?
+        .into_iter()
+        .map(|(seek, player)| SeekEntry {
+            id: seek.id,
+            player: player.name,
+            settings: seek.settings(),
+            created_at: seek.created_at.to_rfc3339(),
+        })
+        .collect();
+    Ok(serde_json::to_string(&SeeksFrame { seeks }).unwrap())
+}
+
+/// Sends the open seeks to just this client, for when they arrive in the lobby.
+pub async fn send_seeks(client: &Client, db: &Db) -> Result<(), DbError> {
+    let payload = seeks_payload(db).await?;
+    if let Some(sender) = &client.sender {
+        if let Err(err) = sender.send(Ok(Message::text(payload))) {
+            eprintln!("error sending seeks to {}: {}", client.username, err);
+        }
+    }
+    Ok(())
+}
+
+/// Takes down the seek of a player who has left, so nobody starts a game with them that
+/// they'll never see, and tells everyone still here.
+pub async fn leave(player_id: i32, clients: &Clients, db: &Db) -> Result<(), DbError> {
+    if db.cancel_player_seek(player_id).await? {
+        println!("Took down the seek of player {}, who left", player_id);
+        broadcast_seeks(clients, db).await?;
+    }
+    Ok(())
+}
+
+/// Sends the open seeks to every connected client.
+pub async fn broadcast_seeks(clients: &Clients, db: &Db) -> Result<(), DbError> {
+    let payload = seeks_payload(db).await?;
+    for client in clients.read().await.values() {
+        if let Some(sender) = &client.sender {
+            if let Err(err) = sender.send(Ok(Message::text(&payload))) {
+                eprintln!("error sending seeks to {}: {}", client.username, err);
+            }
+        }
+    }
+    Ok(())
+}
@@ -11,36 +11,46 @@ use warp::{ws::Message, Filter, Rejection};
 mod db;
 mod game;
 mod handler;
+mod lobby;
+mod sweeper;
 mod ws;
 
 type Result<T> = std::result::Result<T, Rejection>;
 type Clients = Arc<RwLock<HashMap<String, Client>>>;
 type Sockets = Arc<RwLock<HashMap<i32, HashSet<String>>>>;
-type Db = Arc<RwLock<db::Db>>;
+type Db = Arc<dyn db::GameStore>;
 
 #[derive(Debug, Clone)]
 pub struct Client {
     pub username: String,
     pub user_id: i32,
+    pub settings: game::GameSettings,
+    pub difficulty: game::ai::Difficulty,
     pub sender: Option<mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>>,
 }
 
-pub const GAME_SIZE: usize = 7;
-pub const WIN_LENGTH: usize = 4;
-
 #[tokio::main]
 async fn main() {
     env_logger::init();
     info!("starting up");
 
-    let db = match db::Db::db_init().await {
-        Err(err) => panic!("{}", err),
-        Ok(db) => Arc::new(RwLock::new(db)),
+    let db: Db = if env::args().any(|arg| arg == "--in-memory") {
+        println!("Keeping everything in memory, nothing will be saved");
+        Arc::new(db::MemoryStore::new())
+    } else {
+        // Wiping the database is only ever done when asked for
+        let reset = env::args().any(|arg| arg == "--reset-db");
+        match db::Db::db_init(reset).await {
+            Err(err) => panic!("{}", err),
+            Ok(db) => Arc::new(db),
+        }
     };
 
     let clients: Clients = Arc::new(RwLock::new(HashMap::new()));
     let sockets: Sockets = Arc::new(RwLock::new(HashMap::new()));
 
+    tokio::spawn(sweeper::run(db.clone(), clients.clone(), sockets.clone()));
+
     let index_route = warp::path::end().and_then(handler::index_handler);
     let static_route = warp::path("static").and(warp::fs::dir("frontend/dist"));
     let health_route = warp::path!("health").and_then(handler::health_handler);
@@ -58,6 +68,7 @@ async fn main() {
             .and(warp::path::param())
             .and(with_clients(clients.clone()))
             .and(with_sockets(sockets.clone()))
+            .and(with_db(db.clone()))
             .and_then(handler::unregister_handler));
 
     let ws_route = warp::path("ws")
@@ -68,6 +79,41 @@ async fn main() {
         .and(with_db(db.clone()))
         .and_then(handler::ws_handler);
 
+    let analysis_route = warp::path("analysis")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(handler::analysis_handler);
+
+    let history_route = warp::path!("games" / i32 / "moves")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(handler::history_handler);
+
+    let export_route = warp::path!("games" / i32 / "export")
+        .and(warp::get())
+        .and(warp::query())
+        .and(with_db(db.clone()))
+        .and_then(handler::export_handler);
+
+    let import_route = warp::path!("games" / "import")
+        .and(warp::post())
+        .and(warp::header::optional("content-type"))
+        .and(warp::body::bytes())
+        .and(with_db(db.clone()))
+        .and_then(handler::import_handler);
+
+    let stats_route = warp::path!("players" / String / "stats")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(handler::stats_handler);
+
+    let invite_route = warp::path!("invites")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(handler::invite_handler);
+
     let cors = warp::cors()
         .allow_any_origin()
         .allow_methods(vec!["POST", "GET"])
@@ -86,6 +132,12 @@ async fn main() {
         .or(health_route)
         .or(register_routes)
         .or(ws_route)
+        .or(analysis_route)
+        .or(history_route)
+        .or(export_route)
+        .or(import_route)
+        .or(stats_route)
+        .or(invite_route)
         .with(cors);
 
     let host = match env::var("HOST") {
@@ -97,6 +149,19 @@ async fn main() {
     warp::serve(routes).run(socket).await;
 }
 
+/// A number of minutes from the environment variable, or the default if it isn't set or
+/// isn't a number.
+fn env_minutes(name: &str, default: i64) -> chrono::Duration {
+    let minutes = match env::var(name) {
+        Ok(minutes) => minutes.parse().unwrap_or_else(|_| {
+            eprintln!("{name} should be a number of minutes, not {minutes:?}");
+            default
+        }),
+        Err(_) => default,
+    };
+    chrono::Duration::minutes(minutes)
+}
+
 fn with_clients(clients: Clients) -> impl Filter<Extract = (Clients,), Error = Infallible> + Clone {
     warp::any().map(move || clients.clone())
 }
@@ -0,0 +1,145 @@
+//! Ends games that players have walked away from. Every so often, any game nobody has
+//! played in for longer than the timeout is forfeited by whoever's turn it is, and games
+//! that never found a second player are called off. Anyone from those games who is still
+//! connected is sent how it ended. Seeks left up for longer than the timeout are taken
+//! down too.
+
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::db::entities::game;
+use crate::db::DbError;
+use crate::game::{send_game, GameResult};
+use crate::{env_minutes, lobby, Clients, Db, Sockets};
+
+/// How often to look for abandoned games.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a game can sit untouched if `ABANDON_TIMEOUT_MINUTES` isn't set.
+const DEFAULT_ABANDON_TIMEOUT_MINUTES: i64 = 24 * 60;
+
+/// Sweeps away abandoned games for as long as the server runs.
+pub async fn run(db: Db, clients: Clients, sockets: Sockets) {
+    let timeout = abandon_timeout();
+    println!(
+        "Games are forfeited after {} minutes without a move",
+        timeout.num_minutes()
+    );
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = sweep(&db, &clients, &sockets, Utc::now() - timeout).await {
+            eprintln!("error sweeping abandoned games: {}", err);
+        }
+    }
+}
+
+/// Ends every game that's been left alone since `before`, and returns how many it ended.
+/// Seeks posted before then are taken down.
+pub async fn sweep(
+    db: &Db,
+    clients: &Clients,
+    sockets: &Sockets,
+    before: chrono::DateTime<Utc>,
+) -> Result<usize, DbError> {
+    let mut swept = 0;
+    for mut game in db.get_inactive_games(before).await? {
+        if !abandon(&mut game)? {
+            continue;
+        }
+        match db.save_game(game.clone()).await {
+            Ok(()) => (),
+            // Someone played in it after all
+            Err(DbError::Conflict(_)) => continue,
+            Err(err) => return Err(err),
+        }
+        println!(
+            "Swept game {}, ending it as {}",
+            game.id,
+            game.result.as_deref().unwrap_or_default()
+        );
+        swept += 1;
+        send_game(&game, clients, sockets, db).await?;
+    }
+    let expired = db.expire_seeks(before).await?;
+    if expired > 0 {
+        println!("Took down {} stale seeks", expired);
+        lobby::broadcast_seeks(clients, db).await?;
+    }
+    Ok(swept)
+}
+
+/// Ends an abandoned game. Returns whether there was anyone in it to end it for.
+fn abandon(game: &mut game::Model) -> Result<bool, DbError> {
+    if game.player_red_id.is_none() && game.player_black_id.is_none() {
+        return Ok(false);
+    }
+    if game.player_black_id.is_none() {
+        // Waiting for an opponent who never came, so nobody wins
+        game.finish(GameResult::Abandoned, None);
+        return Ok(true);
+    }
+    let board = game.board()?;
+    match board.current_player() {
+        Some(colour) => game.finish(GameResult::Abandoned, Some(colour.other())),
+        // Played out without being marked as finished
+        None => {
+            let winner = board.winner();
+            game.finish(GameResult::from_winner(winner), winner)
+        }
+    }
+    Ok(true)
+}
+
+fn abandon_timeout() -> chrono::Duration {
+    env_minutes("ABANDON_TIMEOUT_MINUTES", DEFAULT_ABANDON_TIMEOUT_MINUTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{start_game, MemoryStore};
+    use crate::game::{Colour, GameSettings};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_forfeits_whoever_is_to_move() {
+        let db: Db = Arc::new(MemoryStore::new());
+        let sockets = Sockets::default();
+        let mut game = start_game(&*db, "alice", "bob").await;
+        let (alice, bob) = (game.player_red_id.unwrap(), game.player_black_id.unwrap());
+        // Alice, as red, has moved, so it's Bob who has left
+        let mut board = game.board().unwrap();
+        board.play(Colour::Red, &"3R".parse().unwrap()).unwrap();
+        game.squares = serde_json::to_value(board.squares()).unwrap();
+        game.ply = 1;
+        db.save_game(game.clone()).await.unwrap();
+        let analysis = db
+            .create_analysis_game(alice, bob, &GameSettings::default(), Utc::now())
+            .await
+            .unwrap();
+        let carol = db.get_player("carol").await.unwrap().id;
+        db.create_seek(carol, &GameSettings::default())
+            .await
+            .unwrap();
+
+        let clients = Clients::default();
+        assert_eq!(
+            sweep(&db, &clients, &sockets, game.active_at.into())
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(db.get_seeks().await.unwrap().len(), 1);
+        let later = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(sweep(&db, &clients, &sockets, later).await.unwrap(), 1);
+
+        let swept = db.find_game(game.id).await.unwrap().unwrap();
+        assert!(swept.finished);
+        assert_eq!(swept.result(), Some(GameResult::Abandoned));
+        assert_eq!(swept.winner_id, Some(alice));
+        assert!(!db.find_game(analysis.id).await.unwrap().unwrap().finished);
+        assert!(db.get_seeks().await.unwrap().is_empty());
+        assert_eq!(sweep(&db, &clients, &sockets, later).await.unwrap(), 0);
+    }
+}
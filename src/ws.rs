@@ -1,33 +1,84 @@
-use crate::game::{play_piece, Direction};
+use crate::game::draw::Draw;
+use crate::game::takeback::Takeback;
+use crate::game::{play_piece, Action, Direction, GameSettings};
+use crate::lobby::{self, lobby_action, LobbyAction};
 use crate::Db;
 use crate::{Client, Clients, Sockets};
 use futures::{FutureExt, StreamExt};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use warp::ws::{Message, WebSocket};
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Play {
-    pub row: usize,
+    /// The row to play into, or the column for `Up` and `Down`, as `direction` says
+    #[serde(alias = "row")]
+    pub line: usize,
     pub direction: Direction,
+    /// Take a piece out from the end of the row that `direction` stacks against
+    /// instead of placing one, where the ruleset allows it
+    #[serde(default)]
+    pub pop: bool,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ClientRequest {
+    /// The game a play or takeback is for, which can be left out by players with only
+    /// one game
+    game_id: Option<i32>,
     play: Option<Play>,
+    takeback: Option<Takeback>,
+    draw: Option<Draw>,
+    #[serde(default)]
+    resign: bool,
+    /// Post a seek for a game on this board
+    seek: Option<GameSettings>,
+    cancel_seek: Option<i32>,
+    accept_seek: Option<i32>,
+    /// An invite code for a private game
+    join: Option<String>,
+}
+
+impl ClientRequest {
+    fn action(&self) -> Option<Action> {
+        match (self.play, self.takeback, self.draw) {
+            (Some(play), _, _) => Some(Action::Play(play)),
+            (None, Some(takeback), _) => Some(Action::Takeback(takeback)),
+            (None, None, Some(draw)) => Some(Action::Draw(draw)),
+            (None, None, None) if self.resign => Some(Action::Resign),
+            (None, None, None) => None,
+        }
+    }
+
+    fn lobby_action(&self) -> Option<LobbyAction> {
+        match (self.seek, self.cancel_seek, self.accept_seek, &self.join) {
+            (Some(settings), _, _, _) => Some(LobbyAction::Seek(settings)),
+            (None, Some(seek_id), _, _) => Some(LobbyAction::CancelSeek(seek_id)),
+            (None, None, Some(seek_id), _) => Some(LobbyAction::AcceptSeek(seek_id)),
+            (None, None, None, Some(code)) => Some(LobbyAction::Join(code.clone())),
+            (None, None, None, None) => None,
+        }
+    }
 }
 
-pub async fn remove_socket(uuid: &String, clients: Clients, sockets: Sockets) {
-    let mut clients = clients.write().await;
-    if let Some(client) = clients.get(uuid) {
-        let user_id = client.user_id;
-        clients.remove(uuid);
+/// Forgets the socket, and once it was the player's last, takes down their seek.
+pub async fn remove_socket(uuid: &String, clients: Clients, sockets: Sockets, db: Db) {
+    let Some(client) = clients.write().await.remove(uuid) else {
+        return;
+    };
+    let mut all_sockets = sockets.write().await;
+    let Some(uuids) = all_sockets.get_mut(&client.user_id) else {
+        return;
+    };
+    uuids.remove(uuid);
+    let last_socket = uuids.is_empty();
+    drop(all_sockets);
 
-        let mut sockets = sockets.write().await;
-        if let Some(uuids) = sockets.get_mut(&user_id) {
-            uuids.remove(uuid);
+    if last_socket {
+        if let Err(err) = lobby::leave(client.user_id, &clients, &db).await {
+            eprintln!("error taking down the seek of {}: {}", client.username, err);
         }
     }
 }
@@ -71,7 +122,7 @@ pub async fn client_connection(
         client_msg(uuid.clone(), msg, &clients, &sockets, &db).await;
     }
 
-    remove_socket(&uuid, clients, sockets).await;
+    remove_socket(&uuid, clients, sockets, db).await;
     println!("{} disconnected at {}", &username, uuid);
 }
 
@@ -95,9 +146,24 @@ async fn client_msg(uuid: String, msg: Message, clients: &Clients, sockets: &Soc
         }
     };
 
-    let clients = clients.write().await;
-    if let Some(client) = clients.get(&uuid) {
-        play_piece(client, &clients, sockets, client_req.play, db).await
+    // Moves can take a while with the AI searching, so the other sockets aren't kept
+    // waiting on the clients for it
+    let client = clients.read().await.get(&uuid).cloned();
+    if let Some(client) = client {
+        match client_req.lobby_action() {
+            Some(action) => lobby_action(&client, clients, sockets, action, db).await,
+            None => {
+                play_piece(
+                    &client,
+                    clients,
+                    sockets,
+                    client_req.game_id,
+                    client_req.action(),
+                    db,
+                )
+                .await
+            }
+        }
     } else {
         eprintln!("No player found with socket id {uuid}");
     }